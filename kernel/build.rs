@@ -7,4 +7,11 @@ fn main() {
     let testdisk_data = vec![0u8; NUM_SECTORS * 512];
 
     std::fs::write("./testdisk.img", testdisk_data).expect("Error writing test disk data");
+
+    // opensbi takes the start of RAM, so the kernel is linked above it
+    if std::env::var_os("CARGO_FEATURE_SBI").is_some() {
+        println!("cargo:rustc-link-arg=-Tsrc/lds/virt_sbi.lds");
+    } else {
+        println!("cargo:rustc-link-arg=-Tsrc/lds/virt.lds");
+    }
 }
@@ -0,0 +1,63 @@
+use core::fmt;
+
+// a calendar date and time in UTC, converted from and to unix time which has no leap seconds
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DateTime {
+    pub year: u32,
+    pub month: u32, // 1 to 12
+    pub day: u32,   // 1 to 31
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+// days from 0000-03-01 to 1970-01-01, the calendar is counted from march so leap days are at the end of a year
+const DAYS_TO_UNIX_EPOCH: u64 = 719468;
+const DAYS_PER_400_YEARS: u64 = 146097;
+
+impl DateTime {
+    // secs is the number of seconds since 1970-01-01 00:00:00 UTC
+    pub const fn from_unix_secs(secs: u64) -> Self {
+        let days = secs / SECONDS_PER_DAY + DAYS_TO_UNIX_EPOCH;
+        let time = secs % SECONDS_PER_DAY;
+
+        let era = days / DAYS_PER_400_YEARS;
+        let day_of_era = days % DAYS_PER_400_YEARS;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+        let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+        let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+
+        Self {
+            year: year as u32,
+            month: month as u32,
+            day: day as u32,
+            hour: (time / 3600) as u32,
+            minute: (time / 60 % 60) as u32,
+            second: (time % 60) as u32,
+        }
+    }
+
+    // dates before 1970 are not supported
+    pub const fn to_unix_secs(&self) -> u64 {
+        let year = self.year as u64 - if self.month <= 2 { 1 } else { 0 };
+        let month = self.month as u64;
+        let era = year / 400;
+        let year_of_era = year % 400;
+        let month_from_march = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * month_from_march + 2) / 5 + self.day as u64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * DAYS_PER_400_YEARS + day_of_era - DAYS_TO_UNIX_EPOCH;
+
+        days * SECONDS_PER_DAY + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
@@ -12,6 +12,7 @@ mod spinlock;
 mod mutable;
 mod malloc;
 mod bitset;
+mod date;
 
 use core::fmt;
 pub use malloc::{free, malloc};
@@ -25,6 +26,7 @@ pub use print::{init_print, print_raw};
 pub use spinlock::Lock;
 pub use mutable::{Mutable, MutableToken};
 pub use bitset::{BitSet, BitSetRaw, bitset_size_bytes};
+pub use date::DateTime;
 pub use malloc::{HEAP_REGION_SIZE};
 use crate::malloc::init_malloc;
 
@@ -118,6 +118,18 @@ impl<T> Vec<T> {
         }
     }
 
+    // shifts the elements after it to the left
+    pub fn remove(&mut self, i: usize) -> T {
+        assert!(i < self.size);
+        unsafe {
+            let ptr = self.get_mut_unchecked(i) as *mut T;
+            let res = core::ptr::read(ptr);
+            core::ptr::copy(ptr.add(1), ptr, self.size - i - 1);
+            self.size -= 1;
+            res
+        }
+    }
+
     pub fn capacity(&self) -> usize {
         self.capacity
     }
@@ -1,14 +1,67 @@
-use crate::riscv::{get_mcounteren, get_menvcfg, get_mhartid, get_mstatus, get_sie, get_sstatus, set_mcounteren, set_medeleg, set_menvcfg, set_mepc, set_mideleg, set_mstatus, set_pmpaddr0, set_pmpcfg0, set_satp, set_sie, set_sstatus, set_tp, MSTATUS_MACHINE, MSTATUS_SUPERVISOR, SIE_EXTERNAL, SIE_SOFTWARE, SIE_TIMER, SSTATUS_SUM};
+#[cfg(not(feature = "sbi"))]
+use crate::riscv::{get_mcounteren, get_menvcfg, get_mhartid, get_mstatus, set_mcounteren, set_medeleg, set_menvcfg, set_mepc, set_mideleg, set_mstatus, set_pmpaddr0, set_pmpcfg0, set_satp, MSTATUS_MACHINE, MSTATUS_SUPERVISOR};
+use crate::riscv::{get_sie, get_sstatus, set_sie, set_sstatus, set_tp, SIE_EXTERNAL, SIE_SOFTWARE, SIE_TIMER, SSTATUS_SUM};
 use core::arch::asm;
+use core::cmp::min;
+#[cfg(not(feature = "sbi"))]
+use core::sync::atomic::{fence, Ordering};
 
 use crate::main;
+use crate::device_tree::{get_machine, parse_device_tree};
+use crate::memory::{get_memory_size, KERNEL_OFFSET};
+#[cfg(not(feature = "sbi"))]
 use crate::timer::machine_mode_timer_init;
+#[cfg(feature = "sbi")]
+use crate::sbi::sbi_hart_start;
+#[cfg(feature = "sbi")]
+use crate::timer::supervisor_timer_init;
 use core::arch::global_asm;
 
-global_asm!(include_str!("asm/entry.S"));
+// without the sbi feature the kernel starts in machine mode with -bios none, with it in supervisor mode under opensbi
+#[cfg(not(feature = "sbi"))]
+global_asm!(include_str!("asm/entry.S"), STACK_SIZE = const STACK_SIZE);
+#[cfg(feature = "sbi")]
+global_asm!(include_str!("asm/entry_sbi.S"), STACK_SIZE = const STACK_SIZE);
 
 pub const STACK_SIZE: usize = 64 * 1024; // 64kB
-pub const NUM_CORES: usize = 4;
+// affinity masks have a bit for every core
+pub const MAX_CORES: usize = 64;
+
+// the harts the machine has, the ones beyond MAX_CORES stay parked
+pub fn get_num_cores() -> usize {
+    min(get_machine().num_harts, MAX_CORES)
+}
+
+// the hart that parsed the device tree and initializes the kernel, opensbi may pick any of them
+static mut BOOT_HART: u64 = 0;
+
+pub fn get_boot_hart() -> u64 {
+    unsafe { BOOT_HART }
+}
+
+fn get_hart_stacks_end() -> u64 {
+    KERNEL_OFFSET + get_memory_size()
+}
+
+// the boot hart runs on the stack in the kernel image, the stacks of the other harts are at the top of RAM
+pub fn get_hart_stacks_start() -> u64 {
+    get_hart_stacks_end() - (get_num_cores() - 1) as u64 * STACK_SIZE as u64
+}
+
+// the first hart after the boot hart gets the highest stack
+#[cfg(feature = "sbi")]
+fn get_hart_stack_end(hart: u64) -> u64 {
+    let index = if hart < get_boot_hart() { hart } else { hart - 1 };
+    get_hart_stacks_end() - index * STACK_SIZE as u64
+}
+
+// entry.S keeps the other harts waiting until hart 0 has read the device tree and sets these
+#[cfg(not(feature = "sbi"))]
+#[no_mangle]
+static mut BOOT_NUM_CORES: u64 = 0;
+#[cfg(not(feature = "sbi"))]
+#[no_mangle]
+static mut HART_STACKS_END: u64 = 0;
 
 pub fn infinite_loop() -> ! {
     loop {
@@ -24,9 +77,36 @@ fn main_caller() -> ! {
     infinite_loop();
 }
 
+// the supervisor mode setup every hart does after the mode specific one
+fn supervisor_entry(hart: u64) -> ! {
+    let mut sie = get_sie();
+    sie |= SIE_EXTERNAL;
+    sie |= SIE_SOFTWARE;
+    sie |= SIE_TIMER;
+    set_sie(sie);
+
+    set_sstatus(get_sstatus() | SSTATUS_SUM);
+
+    // load hartid into tp
+    set_tp(hart);
+
+    main_caller();
+}
+
+// device_tree is the address the firmware passed in a1
+#[cfg(not(feature = "sbi"))]
 #[no_mangle]
-extern "C" fn rust_entry() -> ! {
-    assert!(get_mhartid() < NUM_CORES as u64);
+extern "C" fn rust_entry(device_tree: u64) -> ! {
+    if get_mhartid() == 0 {
+        parse_device_tree(device_tree);
+        unsafe {
+            BOOT_NUM_CORES = get_num_cores() as u64;
+            fence(Ordering::Release);
+            HART_STACKS_END = get_hart_stacks_end();
+        }
+        fence(Ordering::Release);
+    }
+    assert!(get_mhartid() < get_num_cores() as u64);
 
     // set to MODE_SUPERVISOR from MODE_MACHINE
     let mut mstatus = get_mstatus();
@@ -34,8 +114,8 @@ extern "C" fn rust_entry() -> ! {
     mstatus |= MSTATUS_SUPERVISOR;
     set_mstatus(mstatus);
 
-    // set the return address to main caller after mret
-    set_mepc(main_caller as *const () as u64);
+    // set the return address to the supervisor entry after mret
+    set_mepc(machine_mode_return as *const () as u64);
 
     // disable paging
     set_satp(0);
@@ -43,18 +123,11 @@ extern "C" fn rust_entry() -> ! {
     // set interrupts and exceptions to machine mode
     set_medeleg(0xFFFF);
     set_mideleg(0xFFFF);
-    let mut sie = get_sie();
-    sie |= SIE_EXTERNAL;
-    sie |= SIE_SOFTWARE;
-    sie |= SIE_TIMER;
-    set_sie(sie);
 
     // give kernel whole memory
     set_pmpaddr0(0x3fffffffffffff);
     set_pmpcfg0(0xF);
 
-    set_sstatus(get_sstatus() | SSTATUS_SUM);
-
     machine_mode_timer_init();
 
     // enable the sstc extension (i.e. stimecmp).
@@ -63,12 +136,46 @@ extern "C" fn rust_entry() -> ! {
     // allow supervisor to use cycle.
     set_mcounteren(get_mcounteren() | 7);
 
-    // load hartid into tp
-    set_tp(get_mhartid());
-
     unsafe {
         asm!("mret");
     }
 
     infinite_loop();
 }
+
+#[cfg(not(feature = "sbi"))]
+fn machine_mode_return() -> ! {
+    supervisor_entry(get_mhartid());
+}
+
+#[cfg(feature = "sbi")]
+extern "C" {
+    fn secondary_start();
+}
+
+// the firmware starts only this hart, it starts the others with the end of their stacks
+#[cfg(feature = "sbi")]
+#[no_mangle]
+extern "C" fn sbi_entry(hart: u64, device_tree: u64) -> ! {
+    unsafe {
+        BOOT_HART = hart;
+    }
+    parse_device_tree(device_tree);
+    assert!(hart < get_num_cores() as u64);
+
+    for other in 0..get_num_cores() as u64 {
+        if other != hart {
+            assert!(sbi_hart_start(other, secondary_start as *const () as u64, get_hart_stack_end(other)));
+        }
+    }
+
+    supervisor_timer_init();
+    supervisor_entry(hart);
+}
+
+#[cfg(feature = "sbi")]
+#[no_mangle]
+extern "C" fn secondary_entry(hart: u64) -> ! {
+    supervisor_timer_init();
+    supervisor_entry(hart);
+}
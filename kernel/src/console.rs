@@ -1,8 +1,13 @@
-use core::arch::asm;
 use kernel_std::{print, println, String, Vec};
 use crate::disk::filesystem::{list_directory, read_file, write_to_file};
+use crate::fd_table::FdTable;
 use crate::input::{check_for_virtio_input_event, keycode_to_char, EventType};
+use crate::keyboard::{get_foreground_process, push_key_event, release_foreground_process, set_foreground_process, KEY_C, KEY_LEFT_CTRL};
 use crate::print::check_screen_refresh_for_print;
+use crate::memory::PAGE_SIZE;
+use crate::scheduler::{get_idle_ticks, idle, kill_process, list_processes, process_exists, run_program, ProcessInfo};
+use crate::power::{reboot, shutdown};
+use crate::rtc::get_date_time;
 use crate::timer::get_ticks;
 
 fn render_line(line: &String, show_cursor: bool) {
@@ -46,6 +51,178 @@ fn ls_command(parts: &Vec<String>) {
     }
 }
 
+fn ps_command(parts: &Vec<String>) {
+    if parts.size() != 0 {
+        println!("Usage: ps");
+        return;
+    }
+
+    println!("  PID  PPID  STATE    NICE     START      CPU  CORE  PATH");
+    for process in list_processes() {
+        print!("{:>5}", process.pid);
+        if let Some(parent_pid) = process.parent_pid {
+            print!(" {:>5}", parent_pid);
+        } else {
+            print!("     -");
+        }
+        println!("  {:<8} {:>4} {:>9} {:>8} {:>5}  {}", process.state, process.nice, process.start_tick, process.cpu_ticks, process.core, process.path);
+    }
+}
+
+const TOP_INTERVAL: u64 = 1000;
+
+// percentage of the elapsed ticks, a process using a whole core is at 100
+fn print_percentage(ticks: u64, elapsed: u64) {
+    let tenths = ticks * 1000 / elapsed;
+    print!("{:>4}.{}", tenths / 10, tenths % 10);
+}
+
+fn print_top(processes: &Vec<ProcessInfo>, prev_processes: &Vec<ProcessInfo>, idle_ticks: &Vec<u64>, prev_idle_ticks: &Vec<u64>, elapsed: u64) {
+    print!("IDLE");
+    for core in 0..idle_ticks.size() {
+        print!("  core {}:", core);
+        print_percentage(idle_ticks[core] - prev_idle_ticks[core], elapsed);
+        print!("%");
+    }
+    println!();
+
+    // only the time used since the last refresh counts, a new process with a reused pid has a different start tick
+    let mut rows = Vec::new();
+    for process in processes {
+        let mut prev_cpu_ticks = 0;
+        for prev in prev_processes {
+            if prev.pid == process.pid && prev.start_tick == process.start_tick {
+                prev_cpu_ticks = prev.cpu_ticks;
+            }
+        }
+        rows.push((process, process.cpu_ticks - prev_cpu_ticks));
+    }
+    rows.sort(&|a, b| a.1 >= b.1);
+
+    println!("  PID  STATE     CPU%      MEM  PATH");
+    for (process, ticks) in &rows {
+        print!("{:>5}  {:<8}", process.pid, process.state);
+        print_percentage(*ticks, elapsed);
+        if let Some(pages) = process.memory_pages {
+            print!(" {:>7}K", pages * PAGE_SIZE / 1024);
+        } else {
+            print!("        -");
+        }
+        println!("  {}", process.path);
+    }
+    println!();
+}
+
+// refreshes until a key is pressed
+fn top_command(parts: &Vec<String>) {
+    if parts.size() != 0 {
+        println!("Usage: top");
+        return;
+    }
+
+    let mut prev_processes = list_processes();
+    let mut prev_idle_ticks = get_idle_ticks();
+    let mut prev_tick = get_ticks();
+    loop {
+        while get_ticks() - prev_tick < TOP_INTERVAL {
+            check_screen_refresh_for_print();
+            while let Some(event) = check_for_virtio_input_event() {
+                if event.event_type == EventType::Key && event.value == 1 {
+                    return;
+                }
+            }
+            idle();
+        }
+
+        let processes = list_processes();
+        let idle_ticks = get_idle_ticks();
+        let tick = get_ticks();
+        print_top(&processes, &prev_processes, &idle_ticks, &prev_idle_ticks, tick - prev_tick);
+
+        prev_processes = processes;
+        prev_idle_ticks = idle_ticks;
+        prev_tick = tick;
+    }
+}
+
+// the program gets the keyboard until it exits, ctrl+c kills it
+fn run_command(parts: &Vec<String>) {
+    if parts.size() == 0 {
+        println!("Usage: run <path> <optional args>");
+        return;
+    }
+
+    let mut args = parts.clone();
+    args.reverse();
+    let path = args.pop().unwrap();
+    args.reverse();
+
+    let pid = match run_program(&path, &args, &Vec::new(), None, FdTable::new()) {
+        Ok(pid) => pid,
+        Err(err) => {
+            println!("Could not run \"{}\": {:?}", path, err);
+            return;
+        }
+    };
+
+    set_foreground_process(Some(pid));
+    // it may have exited before it got the keyboard
+    if !process_exists(pid) {
+        release_foreground_process(pid);
+    }
+
+    let mut ctrl_pressed = false;
+    while get_foreground_process() == Some(pid) {
+        check_screen_refresh_for_print();
+        while let Some(event) = check_for_virtio_input_event() {
+            if event.event_type != EventType::Key {
+                continue;
+            }
+
+            if event.code == KEY_LEFT_CTRL {
+                ctrl_pressed = event.value != 0;
+            }
+
+            if ctrl_pressed && event.code == KEY_C && event.value == 1 {
+                kill_process(pid);
+            } else {
+                push_key_event(event);
+            }
+        }
+        idle();
+    }
+}
+
+fn kill_command(parts: &Vec<String>) {
+    if parts.size() != 1 {
+        println!("Usage: kill <pid>");
+        return;
+    }
+
+    let mut pid: usize = 0;
+    for c in &parts[0] {
+        // a number too large for a pid is just as invalid
+        let Some(next) = c.to_digit(10).and_then(|digit| pid.checked_mul(10)?.checked_add(digit as usize)) else {
+            println!("Invalid pid: \"{}\"", parts[0]);
+            return;
+        };
+        pid = next;
+    }
+
+    if !kill_process(pid) {
+        println!("No such process: {}", pid);
+    }
+}
+
+fn date_command(parts: &Vec<String>) {
+    if parts.size() != 0 {
+        println!("Usage: date");
+        return;
+    }
+
+    println!("{}", get_date_time());
+}
+
 fn on_command(mut command: String) {
     command.push(' ');
     let mut command_parts = Vec::new();
@@ -75,11 +252,32 @@ fn on_command(mut command: String) {
         println!("  help - show this help");
         println!("  cp <source> <destination> - copy file");
         println!("  ls <optional dir> - list files");
+        println!("  ps - list processes");
+        println!("  top - show cpu usage until a key is pressed");
+        println!("  run <path> <optional args> - run program in the foreground");
+        println!("  kill <pid> - kill process");
+        println!("  date - show the current date and time");
+        println!("  shutdown - write the disk back and turn off the machine");
+        println!("  reboot - write the disk back and restart the machine");
         println!("  exit - exit console");
     } else if command == String::from("cp") {
         cp_command(&command_parts);
     } else if command == String::from("ls") {
         ls_command(&command_parts);
+    } else if command == String::from("ps") {
+        ps_command(&command_parts);
+    } else if command == String::from("top") {
+        top_command(&command_parts);
+    } else if command == String::from("run") {
+        run_command(&command_parts);
+    } else if command == String::from("kill") {
+        kill_command(&command_parts);
+    } else if command == String::from("date") {
+        date_command(&command_parts);
+    } else if command == String::from("shutdown") {
+        shutdown(0);
+    } else if command == String::from("reboot") {
+        reboot();
     } else {
         println!("Unknown command: {}", command);
     }
@@ -132,8 +330,6 @@ pub fn run_console() {
             prev_cursor_cycle = get_ticks();
         }
 
-        unsafe {
-            asm!("wfi");
-        }
+        idle();
     }
 }
\ No newline at end of file
@@ -0,0 +1,279 @@
+use core::cmp::min;
+use core::ptr::addr_of;
+use core::slice::from_raw_parts;
+use crate::memory::KERNEL_OFFSET;
+use crate::virtio::definitions::MAX_VIRTIO_ID;
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+// qemu's tree is 4 levels deep
+const MAX_DEPTH: usize = 16;
+
+#[derive(Clone, Copy)]
+pub struct VirtioSlot {
+    pub base: u64,
+    pub irq: u32,
+}
+
+// the hardware the kernel runs on, until the device tree is parsed it is qemu's virt machine with 128 MB and 4 harts
+pub struct Machine {
+    pub from_device_tree: bool,
+    pub memory_size: u64, // of the RAM starting at KERNEL_OFFSET
+    pub num_harts: usize,
+    pub clint_base: u64,
+    pub plic_base: u64,
+    pub uart_base: u64,
+    pub rtc_base: u64,
+    pub test_base: u64, // the sifive test device that powers off the machine
+    pub timebase_frequency: u64, // how many times per second mtime counts
+    pub virtio_slots: [VirtioSlot; MAX_VIRTIO_ID as usize], // ordered by address
+    pub num_virtio_slots: usize,
+}
+
+const fn qemu_virtio_slots() -> [VirtioSlot; MAX_VIRTIO_ID as usize] {
+    let mut slots = [VirtioSlot { base: 0, irq: 0 }; MAX_VIRTIO_ID as usize];
+    let mut i = 0;
+    while i < slots.len() {
+        slots[i] = VirtioSlot { base: 0x10001000 + 0x1000 * i as u64, irq: i as u32 + 1 };
+        i += 1;
+    }
+    slots
+}
+
+static mut MACHINE: Machine = Machine {
+    from_device_tree: false,
+    memory_size: 128 * 1024 * 1024,
+    num_harts: 4,
+    clint_base: 0x2000000,
+    plic_base: 0x0c000000,
+    uart_base: 0x10000000,
+    rtc_base: 0x101000,
+    test_base: 0x100000,
+    timebase_frequency: 10_000_000,
+    virtio_slots: qemu_virtio_slots(),
+    num_virtio_slots: MAX_VIRTIO_ID as usize,
+};
+
+pub fn get_machine() -> &'static Machine {
+    unsafe { &*addr_of!(MACHINE) }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Device {
+    Memory,
+    Cpu,
+    Clint,
+    Plic,
+    Uart,
+    Rtc,
+    Test,
+    Virtio,
+}
+
+const COMPATIBLE_DEVICES: [(&[u8], Device); 9] = [
+    (b"riscv,clint0", Device::Clint),
+    (b"sifive,clint0", Device::Clint),
+    (b"riscv,plic0", Device::Plic),
+    (b"sifive,plic-1.0.0", Device::Plic),
+    (b"ns16550a", Device::Uart),
+    (b"google,goldfish-rtc", Device::Rtc),
+    (b"sifive,test0", Device::Test),
+    (b"sifive,test1", Device::Test),
+    (b"virtio,mmio", Device::Virtio),
+];
+
+// what is known about a node whose properties are being read
+#[derive(Clone, Copy)]
+struct Node {
+    address_cells: u32, // of the reg properties of its children
+    size_cells: u32,
+    device: Option<Device>,
+    disabled: bool,
+    reg: Option<(u64, u64)>, // the first (address, size)
+    irq: Option<u32>,
+    timebase_frequency: Option<u64>,
+}
+
+impl Node {
+    const fn new() -> Self {
+        Self { address_cells: 2, size_cells: 1, device: None, disabled: false, reg: None, irq: None, timebase_frequency: None }
+    }
+}
+
+const fn read_be32(addr: u64) -> u32 {
+    u32::from_be(unsafe { (addr as *const u32).read_unaligned() })
+}
+
+fn read_cells(value: &[u8], offset: &mut usize, cells: u32) -> Option<u64> {
+    let mut res = 0;
+    for _ in 0..cells {
+        let bytes = value.get(*offset..*offset + 4)?;
+        res = (res << 32) | u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64;
+        *offset += 4;
+    }
+    Some(res)
+}
+
+fn read_u32(value: &[u8]) -> Option<u32> {
+    read_cells(value, &mut 0, 1).map(|res| res as u32)
+}
+
+const fn get_str(addr: u64) -> &'static [u8] {
+    let mut len = 0;
+    while unsafe { *((addr + len) as *const u8) } != 0 {
+        len += 1;
+    }
+    unsafe { from_raw_parts(addr as *const u8, len as usize) }
+}
+
+// string values end with 0, a string list has several of them
+fn strings(value: &[u8]) -> impl Iterator<Item = &[u8]> {
+    value.split(|c| *c == 0).filter(|s| !s.is_empty())
+}
+
+fn parse_property(node: &mut Node, parent: &Node, name: &[u8], value: &[u8]) {
+    match name {
+        b"#address-cells" => node.address_cells = read_u32(value).unwrap_or(node.address_cells),
+        b"#size-cells" => node.size_cells = read_u32(value).unwrap_or(node.size_cells),
+        b"device_type" => {
+            if strings(value).any(|s| s == b"memory") {
+                node.device = Some(Device::Memory);
+            } else if strings(value).any(|s| s == b"cpu") {
+                node.device = Some(Device::Cpu);
+            }
+        }
+        b"compatible" => {
+            for s in strings(value) {
+                if let Some((_, device)) = COMPATIBLE_DEVICES.iter().find(|(compatible, _)| *compatible == s) {
+                    node.device = Some(*device);
+                }
+            }
+        }
+        b"status" => node.disabled = strings(value).any(|s| s == b"disabled"),
+        b"reg" => {
+            let mut offset = 0;
+            if let (Some(address), Some(size)) = (read_cells(value, &mut offset, parent.address_cells), read_cells(value, &mut offset, parent.size_cells)) {
+                node.reg = Some((address, size));
+            }
+        }
+        b"interrupts" => node.irq = read_u32(value),
+        // one or two cells, usually on /cpus for all harts
+        b"timebase-frequency" => node.timebase_frequency = read_cells(value, &mut 0, min(value.len() / 4, 2) as u32).filter(|frequency| *frequency > 0),
+        _ => {}
+    }
+}
+
+fn add_node(machine: &mut Machine, node: &Node) {
+    if node.disabled {
+        return;
+    }
+    if let Some(frequency) = node.timebase_frequency {
+        machine.timebase_frequency = frequency;
+    }
+    if node.device == Some(Device::Cpu) {
+        machine.num_harts += 1;
+        return;
+    }
+    let (Some(device), Some((base, size))) = (node.device, node.reg) else {
+        return;
+    };
+
+    match device {
+        Device::Memory => {
+            if base == KERNEL_OFFSET {
+                machine.memory_size = size;
+            }
+        }
+        Device::Clint => machine.clint_base = base,
+        Device::Plic => machine.plic_base = base,
+        Device::Uart => machine.uart_base = base,
+        Device::Rtc => machine.rtc_base = base,
+        Device::Test => machine.test_base = base,
+        Device::Virtio => {
+            let (Some(irq), true) = (node.irq, machine.num_virtio_slots < MAX_VIRTIO_ID as usize) else {
+                return;
+            };
+            // qemu lists the slots from the last one
+            let mut i = machine.num_virtio_slots;
+            while i > 0 && machine.virtio_slots[i - 1].base > base {
+                machine.virtio_slots[i] = machine.virtio_slots[i - 1];
+                i -= 1;
+            }
+            machine.virtio_slots[i] = VirtioSlot { base, irq };
+            machine.num_virtio_slots += 1;
+        }
+        Device::Cpu => {}
+    }
+}
+
+// addr is the flattened device tree the firmware passes in a1, the defaults stay if it is not valid.
+// called by hart 0 before any other code uses the machine
+pub fn parse_device_tree(addr: u64) -> bool {
+    if addr == 0 || !addr.is_multiple_of(4) || read_be32(addr) != FDT_MAGIC {
+        return false;
+    }
+    let strings_addr = addr + read_be32(addr + 12) as u64;
+
+    let mut machine = Machine {
+        from_device_tree: true,
+        memory_size: 0,
+        num_harts: 0,
+        num_virtio_slots: 0,
+        ..*get_machine()
+    };
+
+    let mut nodes = [Node::new(); MAX_DEPTH];
+    let mut depth = 0;
+    let mut pos = addr + read_be32(addr + 8) as u64;
+    loop {
+        let token = read_be32(pos);
+        pos += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                if depth == MAX_DEPTH {
+                    return false;
+                }
+                pos += (get_str(pos).len() as u64 + 1).next_multiple_of(4);
+                nodes[depth] = Node::new();
+                depth += 1;
+            }
+            FDT_END_NODE => {
+                if depth == 0 {
+                    return false;
+                }
+                depth -= 1;
+                add_node(&mut machine, &nodes[depth]);
+            }
+            FDT_PROP => {
+                if depth == 0 {
+                    return false;
+                }
+                let len = read_be32(pos) as u64;
+                let name = get_str(strings_addr + read_be32(pos + 4) as u64);
+                let value = unsafe { from_raw_parts((pos + 8) as *const u8, len as usize) };
+                pos += 8 + len.next_multiple_of(4);
+
+                // reg is in the cells of the parent, the root's parent has the default ones
+                let parent = if depth >= 2 { nodes[depth - 2] } else { Node::new() };
+                parse_property(&mut nodes[depth - 1], &parent, name, value);
+            }
+            FDT_NOP => {}
+            FDT_END => break,
+            _ => return false,
+        }
+    }
+
+    if machine.memory_size == 0 || machine.num_harts == 0 {
+        return false;
+    }
+    unsafe {
+        MACHINE = machine;
+    }
+    true
+}
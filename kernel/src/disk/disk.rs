@@ -1,4 +1,4 @@
-use crate::virtio::definitions::MAX_VIRTIO_ID;
+use crate::virtio::definitions::get_num_virtio_slots;
 use kernel_std::Vec;
 use crate::virtio::device::VirtioDevice;
 
@@ -95,7 +95,7 @@ impl Disk {
 
 pub fn scan_for_disks() -> Vec<Disk> {
     let mut vec = Vec::new();
-    for id in 0..MAX_VIRTIO_ID {
+    for id in 0..get_num_virtio_slots() {
         if let Some(disk) = get_disk_at(id) {
             vec.push(disk);
         }
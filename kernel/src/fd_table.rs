@@ -0,0 +1,227 @@
+use core::cmp::min;
+use core::ptr::copy_nonoverlapping;
+use kernel_std::{String, Vec};
+use crate::disk::filesystem::{is_directory, is_file, read_file, write_to_file};
+use crate::memory::{copy_from_user, copy_to_user, is_user_range, PageTable};
+use crate::pipe::{add_pipe_reader, add_pipe_writer, close_pipe_reader, close_pipe_writer, create_pipe, read_pipe, write_pipe, PipeStatus};
+
+// the most file descriptors a process can hand to a child it spawns
+const MAX_INHERITED_FDS: usize = 64;
+// writes through a descriptor can not make a file larger than this
+const MAX_FILE_SIZE: usize = 16 * 1024 * 1024;
+
+pub enum FileDescriptor {
+    File { path: String, offset: usize },
+    PipeRead(usize),
+    PipeWrite(usize),
+}
+
+impl FileDescriptor {
+    fn duplicate(&self) -> Self {
+        match self {
+            Self::File { path, offset } => Self::File { path: path.clone(), offset: *offset },
+            Self::PipeRead(pipe) => {
+                add_pipe_reader(*pipe);
+                Self::PipeRead(*pipe)
+            }
+            Self::PipeWrite(pipe) => {
+                add_pipe_writer(*pipe);
+                Self::PipeWrite(*pipe)
+            }
+        }
+    }
+}
+
+impl Drop for FileDescriptor {
+    fn drop(&mut self) {
+        match self {
+            Self::File { .. } => {}
+            Self::PipeRead(pipe) => close_pipe_reader(*pipe),
+            Self::PipeWrite(pipe) => close_pipe_writer(*pipe),
+        }
+    }
+}
+
+pub enum IoStatus {
+    Done(usize),
+    Blocked(usize), // the pipe with this id is empty when reading or full when writing
+    Failed,
+}
+
+impl IoStatus {
+    const fn from_pipe(pipe: usize, status: PipeStatus) -> Self {
+        match status {
+            PipeStatus::Done(size) => Self::Done(size),
+            PipeStatus::Blocked => Self::Blocked(pipe),
+            PipeStatus::Failed => Self::Failed,
+        }
+    }
+}
+
+// every process has its own table, a file descriptor is an index into it
+pub struct FdTable {
+    descriptors: Vec<Option<FileDescriptor>>,
+}
+
+impl FdTable {
+    pub fn new() -> Self {
+        Self { descriptors: Vec::new() }
+    }
+
+    fn insert(&mut self, descriptor: FileDescriptor) -> usize {
+        for (fd, entry) in (&mut self.descriptors).into_iter().enumerate() {
+            if entry.is_none() {
+                *entry = Some(descriptor);
+                return fd;
+            }
+        }
+
+        self.descriptors.push(Some(descriptor));
+        self.descriptors.size() - 1
+    }
+
+    fn get_mut(&mut self, fd: usize) -> Option<&mut FileDescriptor> {
+        self.descriptors.get_mut(fd)?.as_mut()
+    }
+
+    // the child gets duplicates of the parent's descriptors listed in user memory as fds 0, 1, 2...
+    pub fn inherit(parent: &mut Self, page_table: PageTable, fds: u64, count: usize) -> Option<Self> {
+        if count > MAX_INHERITED_FDS {
+            return None;
+        }
+
+        let mut fds_data = [0u8; MAX_INHERITED_FDS * 8];
+        if !copy_from_user(page_table, fds, &mut fds_data[..count * 8]) {
+            return None;
+        }
+
+        let mut res = Self::new();
+        for i in 0..count {
+            let fd = u64::from_le_bytes(fds_data[i * 8..i * 8 + 8].try_into().unwrap());
+            let descriptor = parent.get_mut(fd as usize)?.duplicate();
+            res.insert(descriptor);
+        }
+        Some(res)
+    }
+
+    // a forked child gets duplicates of all descriptors under the same numbers
+    pub fn duplicate(&self) -> Self {
+        let mut res = Self::new();
+        for entry in &self.descriptors {
+            res.descriptors.push(entry.as_ref().map(FileDescriptor::duplicate));
+        }
+        res
+    }
+
+    // if create is set, a missing file is created empty
+    pub fn open(&mut self, path: &String, create: bool) -> Option<usize> {
+        if is_directory(path) {
+            return None;
+        }
+
+        if !is_file(path) {
+            if !create {
+                return None;
+            }
+            write_to_file(path, &Vec::new());
+        }
+
+        Some(self.insert(FileDescriptor::File { path: path.clone(), offset: 0 }))
+    }
+
+    // returns (read end, write end)
+    pub fn pipe(&mut self) -> Option<(usize, usize)> {
+        let pipe = create_pipe()?;
+        let read_fd = self.insert(FileDescriptor::PipeRead(pipe));
+        let write_fd = self.insert(FileDescriptor::PipeWrite(pipe));
+        Some((read_fd, write_fd))
+    }
+
+    pub fn close(&mut self, fd: usize) -> bool {
+        if self.get_mut(fd).is_none() {
+            return false;
+        }
+
+        self.descriptors[fd] = None;
+        true
+    }
+
+    // reads from the current offset into user memory and returns the number of bytes read (0 at the end of file)
+    pub fn read(&mut self, fd: usize, page_table: PageTable, buf: u64, size: usize) -> IoStatus {
+        let Some(descriptor) = self.get_mut(fd) else {
+            return IoStatus::Failed;
+        };
+
+        match descriptor {
+            FileDescriptor::File { path, offset } => {
+                let Some(data) = read_file(path) else {
+                    return IoStatus::Failed;
+                };
+                // the offset may have been moved past the end with seek
+                if *offset >= data.size() {
+                    return IoStatus::Done(0);
+                }
+                let size = min(size, data.size() - *offset);
+                let slice = unsafe { core::slice::from_raw_parts(data.as_ptr().add(*offset), size) };
+                if !copy_to_user(page_table, buf, slice) {
+                    return IoStatus::Failed;
+                }
+
+                *offset += size;
+                IoStatus::Done(size)
+            }
+            FileDescriptor::PipeRead(pipe) => IoStatus::from_pipe(*pipe, read_pipe(*pipe, page_table, buf, size)),
+            FileDescriptor::PipeWrite(_) => IoStatus::Failed,
+        }
+    }
+
+    // writes user memory at the current offset, the file grows if needed
+    pub fn write(&mut self, fd: usize, page_table: PageTable, buf: u64, size: usize) -> IoStatus {
+        let Some(descriptor) = self.get_mut(fd) else {
+            return IoStatus::Failed;
+        };
+
+        match descriptor {
+            FileDescriptor::File { path, offset } => {
+                let Some(end) = offset.checked_add(size).filter(|end| *end <= MAX_FILE_SIZE) else {
+                    return IoStatus::Failed;
+                };
+                // checked before allocating, so a bogus size can not exhaust kernel memory
+                if !is_user_range(page_table, buf, size, false) {
+                    return IoStatus::Failed;
+                }
+
+                let mut new_data = Vec::new_with_size(size);
+                let new_slice = unsafe { core::slice::from_raw_parts_mut(new_data.as_mut_ptr(), size) };
+                if !copy_from_user(page_table, buf, new_slice) {
+                    return IoStatus::Failed;
+                }
+
+                let mut data = read_file(path).unwrap_or_default();
+                while data.size() < end {
+                    data.push(0);
+                }
+                unsafe {
+                    copy_nonoverlapping(new_data.as_ptr(), data.as_mut_ptr().add(*offset), size);
+                }
+                write_to_file(path, &data);
+
+                *offset = end;
+                IoStatus::Done(size)
+            }
+            FileDescriptor::PipeWrite(pipe) => IoStatus::from_pipe(*pipe, write_pipe(*pipe, page_table, buf, size)),
+            FileDescriptor::PipeRead(_) => IoStatus::Failed,
+        }
+    }
+
+    // sets the offset from the start of the file and returns it
+    pub fn seek(&mut self, fd: usize, new_offset: usize) -> Option<usize> {
+        match self.get_mut(fd)? {
+            FileDescriptor::File { offset, .. } => {
+                *offset = new_offset;
+                Some(new_offset)
+            }
+            FileDescriptor::PipeRead(_) | FileDescriptor::PipeWrite(_) => None,
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use kernel_std::Mutable;
+use crate::memory::{copy_from_user, fault_in_page, user_virt_to_phys, PageAccess, PageTable, PhysAddr};
+use crate::scheduler::{block_process, wake_process};
+
+const MAX_WAITERS: usize = 64;
+// returned by futex wait instead of the usual error when every waiter entry is taken, the caller can retry later
+pub const FUTEX_TABLE_FULL: u64 = u64::MAX - 1;
+
+// (physical address, pid) of processes sleeping on a futex, the address is the same for all threads
+static WAITERS: Mutable<[Option<(PhysAddr, usize)>; MAX_WAITERS]> = Mutable::new([None; MAX_WAITERS]);
+
+pub enum FutexStatus {
+    Blocked,
+    ValueChanged, // the value was not the expected one, so the process was not put to sleep
+    TableFull,
+    Failed,
+}
+
+fn get_futex_addr(page_table: PageTable, addr: u64) -> Option<PhysAddr> {
+    // the futex may be on a reserved page that was not accessed yet, or on a copy-on-write page whose frame changes
+    // with the first write, so waiters and wakers have to agree on the copy
+    if !addr.is_multiple_of(4) || !fault_in_page(page_table, addr, PageAccess::Write) {
+        return None;
+    }
+    user_virt_to_phys(page_table, addr, true)
+}
+
+// blocks the process while the 32-bit value at addr equals expected
+pub fn futex_wait(pid: usize, page_table: PageTable, addr: u64, expected: u32) -> FutexStatus {
+    let Some(phys_addr) = get_futex_addr(page_table, addr) else {
+        return FutexStatus::Failed;
+    };
+
+    // the value is checked and the process queued under the lock, so a wake in between is not lost
+    let t = WAITERS.borrow();
+    let waiters = WAITERS.get_mut(&t);
+    // a killed process may have left an entry with its pid behind
+    let slot = (0..MAX_WAITERS).find(|&i| matches!(waiters[i], Some((_, waiter)) if waiter == pid))
+        .or_else(|| (0..MAX_WAITERS).find(|&i| waiters[i].is_none()));
+    let mut value = [0u8; 4];
+    let res = if !copy_from_user(page_table, addr, &mut value) {
+        FutexStatus::Failed
+    } else if u32::from_le_bytes(value) != expected {
+        FutexStatus::ValueChanged
+    } else if let Some(slot) = slot {
+        waiters[slot] = Some((phys_addr, pid));
+        block_process(pid, phys_addr as usize);
+        FutexStatus::Blocked
+    } else {
+        FutexStatus::TableFull
+    };
+    WAITERS.release(t);
+    res
+}
+
+// wakes up to count processes waiting on the futex at addr and returns how many were woken
+pub fn futex_wake(page_table: PageTable, addr: u64, count: usize) -> Option<usize> {
+    let phys_addr = get_futex_addr(page_table, addr)?;
+
+    let t = WAITERS.borrow();
+    let waiters = WAITERS.get_mut(&t);
+    let mut woken = 0;
+    for waiter in waiters.iter_mut() {
+        if woken == count {
+            break;
+        }
+
+        if let Some((waiter_addr, pid)) = *waiter {
+            if waiter_addr == phys_addr {
+                // entries of killed processes are dropped here as well
+                if wake_process(pid, phys_addr as usize) {
+                    woken += 1;
+                }
+                *waiter = None;
+            }
+        }
+    }
+    WAITERS.release(t);
+    Some(woken)
+}
@@ -1,6 +1,6 @@
 use crate::memory::{alloc_continuous_pages, PAGE_SIZE};
 use kernel_std::Mutable;
-use crate::virtio::definitions::MAX_VIRTIO_ID;
+use crate::virtio::definitions::get_num_virtio_slots;
 use crate::virtio::device::VirtioDevice;
 
 const VIRTIO_GPU_CMD_GET_DISPLAY_INFO: u32 = 0x0100;
@@ -251,7 +251,7 @@ impl Gpu {
 static GPU: Mutable<Option<Gpu>> = Mutable::new(None);
 
 pub fn init_gpu() {
-    for id in 0..MAX_VIRTIO_ID {
+    for id in 0..get_num_virtio_slots() {
         if let Some(gpu) = get_gpu_at(id) {
             let t = GPU.borrow();
             *GPU.get_mut(&t) = Some(gpu);
@@ -4,7 +4,8 @@ use core::sync::atomic::{fence, Ordering};
 use crate::memory::{alloc_page, virt_to_phys, VirtAddr, PAGE_SIZE};
 use crate::riscv::get_core_id;
 use crate::spinlock::KernelLock;
-use crate::virtio::definitions::{virtio_reg_read, virtio_reg_write, MmioOffset, VirtqAvail, VirtqDesc, VirtqUsed, MAX_VIRTIO_ID, NUM, VIRTIO_CONFIG_S_ACKNOWLEDGE, VIRTIO_CONFIG_S_DRIVER, VIRTIO_CONFIG_S_DRIVER_OK, VIRTIO_CONFIG_S_FEATURES_OK, VIRTIO_F_ANY_LAYOUT, VIRTIO_MAGIC, VIRTIO_RING_F_EVENT_IDX, VIRTIO_RING_F_INDIRECT_DESC, VRING_DESC_F_WRITE};
+use crate::device_tree::get_machine;
+use crate::virtio::definitions::{get_num_virtio_slots, get_virtio_id, virtio_reg_read, virtio_reg_write, MmioOffset, VirtqAvail, VirtqDesc, VirtqUsed, MAX_VIRTIO_ID, NUM, VIRTIO_CONFIG_S_ACKNOWLEDGE, VIRTIO_CONFIG_S_DRIVER, VIRTIO_CONFIG_S_DRIVER_OK, VIRTIO_CONFIG_S_FEATURES_OK, VIRTIO_F_ANY_LAYOUT, VIRTIO_MAGIC, VIRTIO_RING_F_EVENT_IDX, VIRTIO_RING_F_INDIRECT_DESC, VRING_DESC_F_WRITE};
 
 const EVENT_BUFFER_ELEMENTS: usize = 128;
 const VIRTIO_RING_SIZE: usize = 128;
@@ -175,7 +176,7 @@ impl VirtioInputDevice {
 
     fn catch_up(&mut self) {
         if self.irq_waiting {
-            virtio_input_irq(self.virtio_id as u32);
+            virtio_input_irq(get_machine().virtio_slots[self.virtio_id as usize].irq);
             self.irq_waiting = false;
         }
     }
@@ -208,7 +209,7 @@ impl VirtioInputDevice {
 static mut DEVICES: [Option<VirtioInputDevice>; MAX_VIRTIO_ID as usize] = [const { None }; MAX_VIRTIO_ID as usize];
 
 pub fn init_input_devices() {
-    for id in 0..MAX_VIRTIO_ID {
+    for id in 0..get_num_virtio_slots() {
         if let Some(input_device) = VirtioInputDevice::get_device_at(id) {
             unsafe {
                 DEVICES[id as usize] = Some(input_device);
@@ -218,7 +219,7 @@ pub fn init_input_devices() {
 }
 
 pub fn check_for_virtio_input_event() -> Option<InputEvent> {
-    for id in 0..MAX_VIRTIO_ID {
+    for id in 0..get_num_virtio_slots() {
         if let Some(device) = unsafe { &mut DEVICES[id as usize] } {
             if let Some(event) = device.receive_input() {
                 return Some(event);
@@ -229,10 +230,9 @@ pub fn check_for_virtio_input_event() -> Option<InputEvent> {
 }
 
 pub fn virtio_input_irq(irq: u32) {
-    if irq == 0 || irq > MAX_VIRTIO_ID as u32 {
+    let Some(id) = get_virtio_id(irq) else {
         return;
-    }
-    let id = irq as u64 - 1;
+    };
 
     if let Some(device) = unsafe { &mut DEVICES[id as usize] } {
         if device.lock.locked_by() == get_core_id() as i32 {
@@ -0,0 +1,115 @@
+use kernel_std::Mutable;
+use crate::input::{keycode_to_char, EventType, InputEvent};
+use crate::wait_queue::WaitQueue;
+
+const KEY_QUEUE_SIZE: usize = 64;
+
+pub const KEY_ENTER: u16 = 28;
+pub const KEY_BACKSPACE: u16 = 14;
+pub const KEY_LEFT_CTRL: u16 = 29;
+pub const KEY_C: u16 = 46;
+
+// key events for the process in the foreground, the console hands them over
+struct KeyQueue {
+    foreground: Option<usize>, // the pid that gets the keyboard, the console keeps it if None
+    events: [InputEvent; KEY_QUEUE_SIZE],
+    start: usize,
+    size: usize,
+}
+
+static KEYS: Mutable<KeyQueue> = Mutable::new(KeyQueue {
+    foreground: None,
+    events: [InputEvent { event_type: EventType::Syn, code: 0, value: 0 }; KEY_QUEUE_SIZE],
+    start: 0,
+    size: 0,
+});
+
+// woken when a key arrives for the foreground process
+static KEY_WAITERS: WaitQueue = WaitQueue::new();
+
+pub enum KeyStatus {
+    Event(InputEvent),
+    Empty,
+    NotForeground,
+}
+
+// keys that were meant for the previous process are dropped
+pub fn set_foreground_process(pid: Option<usize>) {
+    let t = KEYS.borrow();
+    let keys = KEYS.get_mut(&t);
+    keys.foreground = pid;
+    keys.start = 0;
+    keys.size = 0;
+    KEYS.release(t);
+}
+
+pub fn get_foreground_process() -> Option<usize> {
+    let t = KEYS.borrow();
+    let res = KEYS.get(&t).foreground;
+    KEYS.release(t);
+    res
+}
+
+// called when a process is released, the console gets the keyboard back if it was in the foreground
+pub fn release_foreground_process(pid: usize) {
+    let t = KEYS.borrow();
+    let keys = KEYS.get_mut(&t);
+    if keys.foreground == Some(pid) {
+        keys.foreground = None;
+        keys.size = 0;
+    }
+    KEYS.release(t);
+}
+
+// returns false if no process is in the foreground, keys are dropped while the queue is full
+pub fn push_key_event(event: InputEvent) -> bool {
+    let t = KEYS.borrow();
+    let keys = KEYS.get_mut(&t);
+    let res = keys.foreground.is_some();
+    if res && keys.size < KEY_QUEUE_SIZE {
+        keys.events[(keys.start + keys.size) % KEY_QUEUE_SIZE] = event;
+        keys.size += 1;
+    }
+    KEYS.release(t);
+
+    KEY_WAITERS.wake_all();
+    res
+}
+
+// pid is the main thread of the calling process
+pub fn read_key(pid: usize) -> KeyStatus {
+    let t = KEYS.borrow();
+    let keys = KEYS.get_mut(&t);
+    let res = if keys.foreground != Some(pid) {
+        KeyStatus::NotForeground
+    } else if keys.size == 0 {
+        KeyStatus::Empty
+    } else {
+        let event = keys.events[keys.start];
+        keys.start = (keys.start + 1) % KEY_QUEUE_SIZE;
+        keys.size -= 1;
+        KeyStatus::Event(event)
+    };
+    KEYS.release(t);
+    res
+}
+
+// blocks the thread until there is a key for the process, a process in the background waits until it is in the foreground
+pub fn wait_for_key(pid: usize, owner: usize) {
+    KEY_WAITERS.wait_until(pid, &|| {
+        let t = KEYS.borrow();
+        let keys = KEYS.get(&t);
+        let res = keys.foreground == Some(owner) && keys.size > 0;
+        KEYS.release(t);
+        res
+    });
+}
+
+// the character a program reading text gets for the key
+pub const fn key_event_char(event: InputEvent) -> Option<char> {
+    match event.code {
+        KEY_ENTER => Some('\n'),
+        KEY_BACKSPACE => Some('\x08'),
+        code => keycode_to_char(code),
+    }
+}
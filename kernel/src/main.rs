@@ -2,9 +2,10 @@
 #![no_main]
 #![allow(non_camel_case_types)]
 
-use crate::boot::infinite_loop;
+use crate::boot::{get_boot_hart, get_num_cores, infinite_loop};
+use crate::device_tree::get_machine;
 use crate::disk::disk::{Disk, scan_for_disks};
-use crate::memory::{get_num_free_pages, init_paging, init_paging_hart, KERNEL_VIRTUAL_END, NUM_PAGES};
+use crate::memory::{get_num_free_pages, init_paging, init_paging_hart, KERNEL_VIRTUAL_END, get_memory_size, get_num_pages};
 use crate::print::{init_print, set_print_color};
 use crate::riscv::{enable_fpu, get_core_id, interrupts_enable};
 use crate::trap::switch_to_kernel_trap;
@@ -39,6 +40,16 @@ mod console;
 mod scheduler;
 mod text_renderer;
 mod elf;
+mod fd_table;
+mod pipe;
+mod futex;
+mod wait_queue;
+mod keyboard;
+mod rtc;
+mod device_tree;
+mod power;
+#[cfg(feature = "sbi")]
+mod sbi;
 
 pub const ROOT_MAGIC: u32 = 0x63726591;
 
@@ -59,10 +70,18 @@ fn find_root_disk(disks: &mut Vec<Disk>) -> Disk {
     panic!("Root disk not found")
 }
 
+fn print_machine() {
+    let machine = get_machine();
+    if !machine.from_device_tree {
+        println!("No device tree found, assuming qemu's virt machine");
+    }
+    println!("{} of {} harts used, {} MB of RAM, {} virtio slots", get_num_cores(), machine.num_harts, get_memory_size() / (1024 * 1024), machine.num_virtio_slots);
+}
+
 pub fn main() {
     static mut INITIALIZED: bool = false;
 
-    if get_core_id() == 0 {
+    if get_core_id() == get_boot_hart() {
         switch_to_kernel_trap();
         interrupts_enable(true);
         enable_fpu();
@@ -75,7 +94,8 @@ pub fn main() {
         init_text_renderer();
         let mut disks = scan_for_disks();
 
-        println!("Initializing kernel with core 0");
+        println!("Initializing kernel with core {}", get_core_id());
+        print_machine();
 
         init_scheduler();
         init_input_devices();
@@ -112,8 +132,8 @@ pub fn main() {
             toggle_scheduler(true);
         }
 
-        let all_memory = (NUM_PAGES * 4) as f32 / 1000.0;
-        let used_memory = ((NUM_PAGES - get_num_free_pages()) * 4) as f32 / 1000.0;
+        let all_memory = (get_num_pages() * 4) as f32 / 1000.0;
+        let used_memory = ((get_num_pages() - get_num_free_pages()) * 4) as f32 / 1000.0;
         let portion = used_memory / all_memory * 100.0;
         println!("{used_memory} MB / {all_memory} MB of RAM used ({portion:.1}%)");
 
@@ -1,11 +1,8 @@
 mod bitset;
 mod paging;
 
-pub const MEMORY_SIZE: u64 = 128 * 1024 * 1024;
-
 pub const PAGE_SIZE: u64 = 4096;
 pub const KERNEL_OFFSET: u64 = 0x80000000;
-pub const NUM_PAGES: u64 = MEMORY_SIZE / PAGE_SIZE;
 
 pub const KERNEL_PT_ROOT_ENTRIES: u64 = 13; // how many entries are used for kernel page table (all the rest is for user page tables)
 
@@ -29,17 +26,50 @@ const _: [(); (KERNEL_VIRTUAL_TOP - KERNEL_VIRTUAL_END) as usize] = [(); (KERNEL
 // where the user context is stored (registers have to be saved when user program is interrupted)
 pub const USER_CONTEXT: u64 = KERNEL_VIRTUAL_TOP;
 
+// nothing is ever mapped between the context page and the stack, so a stack that overflows faults here
+pub const USER_STACK_GUARD: u64 = USER_CONTEXT + PAGE_SIZE;
+pub const USER_STACK_GUARD_SIZE: u64 = 16 * PAGE_SIZE;
+
+// the stack of the main thread starts with USER_STACK_SIZE reserved below USER_STACK_END and grows down
+// on faults up to the stack limit of the process, threads have stacks of USER_STACK_SIZE
 pub const USER_STACK_SIZE: u64 = 32 * PAGE_SIZE;
-// where users program stack lives
-pub const USER_STACK: u64 = USER_CONTEXT + PAGE_SIZE;
+pub const USER_STACK: u64 = USER_STACK_GUARD + USER_STACK_GUARD_SIZE;
+pub const USER_STACK_MAX_SIZE: u64 = 2048 * PAGE_SIZE;
+pub const USER_STACK_END: u64 = USER_STACK + USER_STACK_MAX_SIZE;
+pub const DEFAULT_STACK_LIMIT: u64 = 256 * PAGE_SIZE;
+
+// where the arguments and environment of the program are stored, right after the stack
+pub const USER_ARGS: u64 = USER_STACK_END;
+pub const USER_ARGS_SIZE: u64 = 4 * PAGE_SIZE;
+
+// every thread other than the main one gets a context page followed by its stack here, indexed by its pid
+pub const USER_THREADS: u64 = USER_ARGS + USER_ARGS_SIZE;
+pub const USER_THREAD_SIZE: u64 = PAGE_SIZE + USER_STACK_SIZE;
+pub const USER_THREADS_END: u64 = USER_CONTEXT + FRAME_SIZE;
+
+// user programs can only access memory between USER_STACK and USER_VIRTUAL_END
+pub const USER_VIRTUAL_END: u64 = 1 << 38;
 
+use core::cmp::min;
 use kernel_std::HEAP_REGION_SIZE;
-pub use paging::{refresh_paging, alloc_page, clear_page_table, alloc_continuous_pages, free_page, get_num_free_pages, init_paging, init_paging_hart, map_page, map_page_auto, unmap_page, virt_to_phys, PhysAddr, VirtAddr, PageTable, create_page_table, switch_to_page_table};
+use crate::device_tree::get_machine;
+pub use paging::{refresh_paging, alloc_page, clear_page_table, alloc_continuous_pages, get_num_free_pages, init_paging, init_paging_hart, map_page, map_page_auto, unmap_page, virt_to_phys, PhysAddr, VirtAddr, PageTable, create_page_table, switch_to_page_table, get_current_page_table, is_user_addr, user_virt_to_phys, copy_from_user, copy_to_user, copy_str_from_user, is_user_range, is_page_mapped, count_user_pages, reserve_page, is_page_reserved, fault_in_page, take_user_page, take_replaced_pages, release_page, PageAccess, clone_user_pages};
+#[cfg(feature = "run_tests")]
+pub use paging::{free_page, get_page_refs, unmap_user_page};
 
 extern "C" {
     pub static _end: u8;
 }
 
+// the RAM starting at KERNEL_OFFSET, only what is identity mapped can be used
+pub fn get_memory_size() -> u64 {
+    min(get_machine().memory_size, ID_MAP_END - KERNEL_OFFSET)
+}
+
+pub fn get_num_pages() -> u64 {
+    get_memory_size() / PAGE_SIZE
+}
+
 pub fn get_kernel_top_address() -> u64 {
     unsafe { &_end as *const u8 as u64 + 30 * PAGE_SIZE }
 }
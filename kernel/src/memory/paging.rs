@@ -2,13 +2,16 @@ pub type PhysAddr = u64;
 pub type VirtAddr = *mut u8;
 
 use core::arch::asm;
-use crate::boot::{NUM_CORES, STACK_SIZE};
+use crate::boot::{get_hart_stacks_start, MAX_CORES};
 use kernel_std::{bitset_size_bytes, debugln, BitSetRaw};
-use crate::memory::{get_kernel_top_address, HEAP_ADDR, ID_MAP_END, KERNEL_OFFSET, KERNEL_PT_ROOT_ENTRIES, NUM_PAGES, PAGE_SIZE};
+use crate::memory::{get_kernel_top_address, HEAP_ADDR, ID_MAP_END, KERNEL_OFFSET, KERNEL_PT_ROOT_ENTRIES, get_num_pages, PAGE_SIZE, USER_STACK, USER_VIRTUAL_END};
 use crate::riscv::{get_core_id, get_satp, set_satp};
+use core::cmp::min;
 use core::intrinsics::write_bytes;
-use core::sync::atomic::{fence, Ordering};
-use kernel_std::init_std_memory;
+use core::ptr::{addr_of_mut, copy_nonoverlapping};
+use core::ops::Range;
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+use kernel_std::{init_std_memory, String, Vec};
 use kernel_std::Mutable;
 
 pub static SEGMENTS_BITSET: Mutable<BitSetRaw> = Mutable::new(BitSetRaw::new_empty());
@@ -21,21 +24,26 @@ pub fn get_num_free_pages() -> u64 {
 }
 
 pub fn alloc_page() -> PhysAddr {
+    let Some(page) = try_alloc_page() else {
+        panic!("Out of memory");
+    };
+    page
+}
+
+// None if there is no free page, for allocations the kernel can recover from
+pub fn try_alloc_page() -> Option<PhysAddr> {
     let t = SEGMENTS_BITSET.borrow();
     let index = SEGMENTS_BITSET.get_mut(&t).get_zero_element();
     if let Some(index) = index {
         SEGMENTS_BITSET.get_mut(&t).set(index, true);
-
-        SEGMENTS_BITSET.release(t);
-        index as u64 * PAGE_SIZE + KERNEL_OFFSET
-    } else {
-        panic!("Out of memory");
     }
+    SEGMENTS_BITSET.release(t);
+    index.map(|index| index as u64 * PAGE_SIZE + KERNEL_OFFSET)
 }
 
 pub fn alloc_continuous_pages(num: u64) -> PhysAddr {
     let t = SEGMENTS_BITSET.borrow();
-    for i in 0..=NUM_PAGES - num {
+    for i in 0..=get_num_pages() - num {
         let mut all_free = true;
         for j in 0..num {
             if SEGMENTS_BITSET.get(&t).get(i as usize + j as usize) {
@@ -66,6 +74,37 @@ pub fn free_page(addr: PhysAddr) {
     SEGMENTS_BITSET.release(t);
 }
 
+// how many more page tables map a physical page than the one it was allocated for, 0 for most pages.
+// the entries sharing a page are changed under this lock together with the count
+static PAGE_REFS: Mutable<Option<Vec<u32>>> = Mutable::new(None);
+
+const fn get_page_index(addr: PhysAddr) -> usize {
+    ((addr - KERNEL_OFFSET) / PAGE_SIZE) as usize
+}
+
+// a shared page is only freed when the last page table releases it
+pub fn release_page(addr: PhysAddr) {
+    let t = PAGE_REFS.borrow();
+    let refs = &mut PAGE_REFS.get_mut(&t).as_mut().unwrap()[get_page_index(addr)];
+    let shared = *refs > 0;
+    if shared {
+        *refs -= 1;
+    }
+    PAGE_REFS.release(t);
+
+    if !shared {
+        free_page(addr);
+    }
+}
+
+#[cfg(feature = "run_tests")]
+pub fn get_page_refs(addr: PhysAddr) -> u32 {
+    let t = PAGE_REFS.borrow();
+    let res = PAGE_REFS.get(&t).as_ref().unwrap()[get_page_index(addr)];
+    PAGE_REFS.release(t);
+    res
+}
+
 fn page_allocator(page: VirtAddr, ignore_if_exists: bool) {
     map_page_auto(page, ignore_if_exists, true, false, false);
 }
@@ -79,24 +118,21 @@ fn page_deallocator(page: VirtAddr) {
 pub fn init_paging() {
     // for now just add 20 pages because apparently kernel writes after the end for some reason
     let kernel_end = (get_kernel_top_address() - 1) / PAGE_SIZE * PAGE_SIZE;
-    let bitset_size_bytes = bitset_size_bytes(NUM_PAGES as usize);
+    let bitset_size_bytes = bitset_size_bytes(get_num_pages() as usize);
     let bitset_size_pages = (bitset_size_bytes as u64).div_ceil(PAGE_SIZE);
     let kernel_size_pages = (kernel_end - KERNEL_OFFSET) / PAGE_SIZE;
 
     let t = SEGMENTS_BITSET.borrow();
-    *SEGMENTS_BITSET.get_mut(&t) = BitSetRaw::new(NUM_PAGES as usize, kernel_end as *mut u64);
+    *SEGMENTS_BITSET.get_mut(&t) = BitSetRaw::new(get_num_pages() as usize, kernel_end as *mut u64);
 
     // mark kernel and bitset pages as taken
     for i in 0..bitset_size_pages + kernel_size_pages {
         SEGMENTS_BITSET.get_mut(&t).set(i as usize, true);
     }
 
-    let stack_size = NUM_CORES * STACK_SIZE;
-    let stack_size_pages = (stack_size as u64).div_ceil(PAGE_SIZE);
-
-    // mark stack pages as taken
-    for i in 0..stack_size_pages {
-        SEGMENTS_BITSET.get_mut(&t).set((NUM_PAGES - 1 - i) as usize, true);
+    // mark the stacks of the other harts as taken
+    for i in (get_hart_stacks_start() - KERNEL_OFFSET) / PAGE_SIZE..get_num_pages() {
+        SEGMENTS_BITSET.get_mut(&t).set(i as usize, true);
     }
     SEGMENTS_BITSET.release(t);
 
@@ -118,6 +154,10 @@ pub fn init_paging() {
     }
 
     init_std_memory(&page_allocator, &page_deallocator, HEAP_ADDR);
+
+    let t = PAGE_REFS.borrow();
+    *PAGE_REFS.get_mut(&t) = Some(Vec::new_with_size(get_num_pages() as usize));
+    PAGE_REFS.release(t);
 }
 
 pub fn init_paging_hart() {
@@ -134,10 +174,17 @@ pub const PTE_READ: u64 = 1 << 1;
 pub const PTE_WRITE: u64 = 1 << 2;
 pub const PTE_EXECUTE: u64 = 1 << 3;
 pub const PTE_USER: u64 = 1 << 4;
+// the hardware ignores entries without PTE_PRESENT, this one marks a page that gets memory on its first access
+pub const PTE_LAZY: u64 = 1 << 8;
+// a page shared with other page tables after a fork, it is mapped read-only and copied on the first write
+pub const PTE_COW: u64 = 1 << 9;
+
+const PTE_FLAGS: u64 = PTE_READ | PTE_WRITE | PTE_EXECUTE | PTE_USER;
 
 const PAGE_TABLE_SIZE: usize = 512;
 
-static mut CURRENT_PAGE_TABLE: [PageTable; NUM_CORES] = [0 as PageTable; NUM_CORES];
+const SATP_PPN: u64 = (1 << 44) - 1;
+
 static mut KERNEL_PAGE_TABLE: PageTable = 0 as PageTable;
 
 pub fn create_page_table() -> PageTable {
@@ -167,7 +214,7 @@ fn destroy_page_table(page_table: PageTable) {
         if is_entry_table(entry) {
             destroy_page_table(get_entry_addr(entry).unwrap());
         } else if is_entry_leaf(entry) {
-            free_page(get_entry_addr(entry).unwrap() as PhysAddr);
+            release_page(get_entry_addr(entry).unwrap() as PhysAddr);
         }
     }
     free_page(page_table as PhysAddr);
@@ -196,16 +243,18 @@ pub fn clear_page_table(page_table: PageTable) {
 pub fn switch_to_page_table(page_table: PageTable) {
     debug_assert_eq!(page_table as u64 % PAGE_SIZE, 0);
     fence(Ordering::Release);
-    unsafe {
-        if CURRENT_PAGE_TABLE[get_core_id() as usize] == page_table {
-            return;
-        }
-        CURRENT_PAGE_TABLE[get_core_id() as usize] = page_table;
+    if get_current_page_table() == page_table {
+        return;
     }
     set_satp((page_table as u64 / PAGE_SIZE) | (8u64 << 60));
     fence(Ordering::Release);
 }
 
+// satp holds the physical page number of the page table of the core
+pub fn get_current_page_table() -> PageTable {
+    ((get_satp() & SATP_PPN) * PAGE_SIZE) as PageTable
+}
+
 pub fn refresh_paging() {
     unsafe {
         asm!("sfence.vma zero, zero", options(nostack, preserves_flags));
@@ -239,7 +288,7 @@ fn create_page_table_entry(addr: PhysAddr) -> PageTableEntry {
 }
 
 fn get_address_page_table_entry(virtual_addr: VirtAddr) -> Option<&'static mut PageTableEntry> {
-    let mut curr_table = unsafe { CURRENT_PAGE_TABLE[get_core_id() as usize] };
+    let mut curr_table = get_current_page_table();
     for i in 0..2 {
         let index = (virtual_addr as u64 >> (30 - 9 * i)) & 0b111111111;
         unsafe {
@@ -262,6 +311,20 @@ fn get_address_page_table_entry(virtual_addr: VirtAddr) -> Option<&'static mut P
     Some(get_sub_page_table_entry(curr_table, index as usize))
 }
 
+const fn get_entry_flags(writable: bool, user: bool, executable: bool) -> u64 {
+    let mut flags = PTE_READ;
+    if writable {
+        flags |= PTE_WRITE;
+    }
+    if user {
+        flags |= PTE_USER;
+    }
+    if executable {
+        flags |= PTE_EXECUTE;
+    }
+    flags
+}
+
 #[allow(clippy::fn_params_excessive_bools)]
 pub fn map_page(virtual_addr: VirtAddr, physical_addr: PhysAddr, ignore_if_exists: bool, writable: bool, user: bool, executable: bool) {
     let curr_entry = get_address_page_table_entry(virtual_addr).unwrap();
@@ -269,16 +332,16 @@ pub fn map_page(virtual_addr: VirtAddr, physical_addr: PhysAddr, ignore_if_exist
         return;
     }
     debug_assert_eq!(*curr_entry & PTE_PRESENT, 0);
-    *curr_entry = create_page_table_entry(physical_addr) | PTE_READ;
-    if writable {
-        *curr_entry |= PTE_WRITE;
-    }
-    if user {
-        *curr_entry |= PTE_USER;
-    }
-    if executable {
-        *curr_entry |= PTE_EXECUTE;
+    *curr_entry = create_page_table_entry(physical_addr) | get_entry_flags(writable, user, executable);
+}
+
+// the page gets zeroed memory when it is first accessed, a page that is mapped or reserved already stays as it is
+pub fn reserve_page(virtual_addr: VirtAddr, writable: bool, user: bool, executable: bool) {
+    let curr_entry = get_address_page_table_entry(virtual_addr).unwrap();
+    if (*curr_entry & (PTE_PRESENT | PTE_LAZY)) != 0 {
+        return;
     }
+    *curr_entry = PTE_LAZY | get_entry_flags(writable, user, executable);
 }
 
 #[allow(clippy::fn_params_excessive_bools)]
@@ -301,3 +364,358 @@ pub fn unmap_page(virtual_addr: VirtAddr) {
     *curr_entry = 0;
     refresh_paging();
 }
+
+pub const fn is_user_addr(addr: u64) -> bool {
+    addr >= USER_STACK && addr < USER_VIRTUAL_END
+}
+
+// walks the page table without allocating anything and returns the last level entry for the address.
+// it is changed atomically, threads of the process fault pages in without holding a lock
+fn find_page_table_entry_atomic(page_table: PageTable, virtual_addr: u64) -> Option<&'static AtomicU64> {
+    let mut curr_table = page_table;
+    for i in 0..2 {
+        let index = (virtual_addr >> (30 - 9 * i)) & 0b111111111;
+        let entry = *get_sub_page_table_entry(curr_table, index as usize);
+        if !is_entry_table(entry) {
+            return None;
+        }
+        curr_table = get_entry_addr(entry)?;
+    }
+
+    let index = (virtual_addr >> 12) & 0b111111111;
+    Some(unsafe { AtomicU64::from_ptr(curr_table.add(index as usize)) })
+}
+
+// the leaf entry for the address, None if it is not mapped
+fn find_page_table_entry(page_table: PageTable, virtual_addr: u64) -> Option<PageTableEntry> {
+    let entry = find_page_table_entry_atomic(page_table, virtual_addr)?.load(Ordering::Acquire);
+    if is_entry_leaf(entry) {
+        Some(entry)
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PageAccess {
+    Read,
+    Write,
+    Execute,
+}
+
+impl PageAccess {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Execute => "execute",
+        }
+    }
+
+    const fn get_flags(self) -> u64 {
+        match self {
+            Self::Read => PTE_USER | PTE_READ,
+            Self::Write => PTE_USER | PTE_WRITE,
+            Self::Execute => PTE_USER | PTE_EXECUTE,
+        }
+    }
+}
+
+// true if the user may access the page this way, a reserved page gets its memory now. threads of the process
+// may fault on the same page at once, the first one maps it and the others free the page they allocated
+pub fn fault_in_page(page_table: PageTable, addr: u64, access: PageAccess) -> bool {
+    if !is_user_addr(addr) {
+        return false;
+    }
+    let Some(entry) = find_page_table_entry_atomic(page_table, addr) else {
+        return false;
+    };
+
+    loop {
+        let value = entry.load(Ordering::Acquire);
+        // a copy-on-write page is writable, it just has to be copied first
+        let allowed = if (value & PTE_COW) != 0 { value | PTE_WRITE } else { value };
+        if (allowed & access.get_flags()) != access.get_flags() {
+            return false;
+        }
+        if (value & PTE_PRESENT) != 0 {
+            if access != PageAccess::Write || (value & PTE_COW) == 0 {
+                return true;
+            }
+            match copy_on_write(entry, value) {
+                Some(true) => return true,
+                Some(false) => continue,
+                None => return false,
+            }
+        }
+        if (value & PTE_LAZY) == 0 {
+            return false;
+        }
+        let Some(page) = try_alloc_page() else {
+            return false;
+        };
+        unsafe {
+            write_bytes(page as *mut u8, 0, PAGE_SIZE as usize);
+        }
+        let mapped = create_page_table_entry(page) | (value & PTE_FLAGS);
+        if entry.compare_exchange(value, mapped, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            return true;
+        }
+        free_page(page);
+    }
+}
+
+// the shared pages copy_on_write replaced on every core, they are released with take_replaced_pages and release_page
+static mut REPLACED_PAGES: [Option<Vec<PhysAddr>>; MAX_CORES] = [const { None }; MAX_CORES];
+
+pub fn take_replaced_pages() -> Vec<PhysAddr> {
+    unsafe { (*addr_of_mut!(REPLACED_PAGES))[get_core_id() as usize].take().unwrap_or_default() }
+}
+
+// the entry gets its own copy of the shared page, or the page itself if the other page tables do not use it anymore.
+// None if there is no memory for the copy, Some(false) if the entry was changed in the meantime
+fn copy_on_write(entry: &AtomicU64, value: PageTableEntry) -> Option<bool> {
+    let old_page = get_entry_addr(value).unwrap() as PhysAddr;
+    let writable = (value | PTE_WRITE) & !PTE_COW;
+
+    let t = PAGE_REFS.borrow();
+    let refs = &mut PAGE_REFS.get_mut(&t).as_mut().unwrap()[get_page_index(old_page)];
+    let res = if *refs == 0 {
+        Some(entry.compare_exchange(value, writable, Ordering::AcqRel, Ordering::Acquire).is_ok())
+    } else if let Some(page) = try_alloc_page() {
+        unsafe {
+            copy_nonoverlapping(old_page as *const u8, page as *mut u8, PAGE_SIZE as usize);
+        }
+        let copied = create_page_table_entry(page) | (writable & PTE_FLAGS);
+        if entry.compare_exchange(value, copied, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            // the reference is only dropped once other cores can not read the page through their tlb anymore
+            unsafe {
+                (*addr_of_mut!(REPLACED_PAGES))[get_core_id() as usize].get_or_insert_with(Vec::new).push(old_page);
+            }
+            Some(true)
+        } else {
+            free_page(page);
+            Some(false)
+        }
+    } else {
+        None
+    };
+    PAGE_REFS.release(t);
+    res
+}
+
+// the entry of the other page table gets the same page, a writable one becomes copy-on-write in both
+fn share_entry(entry: &AtomicU64) -> PageTableEntry {
+    let t = PAGE_REFS.borrow();
+    let res = loop {
+        let value = entry.load(Ordering::Acquire);
+        // a reserved page gets separate memory in every page table that accesses it
+        let Some(page) = get_entry_addr(value) else {
+            break value;
+        };
+        let shared = if (value & PTE_WRITE) != 0 { (value & !PTE_WRITE) | PTE_COW } else { value };
+        if entry.compare_exchange(value, shared, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            PAGE_REFS.get_mut(&t).as_mut().unwrap()[get_page_index(page as PhysAddr)] += 1;
+            break shared;
+        }
+    };
+    PAGE_REFS.release(t);
+    res
+}
+
+// returns a copy of a table on the given level and the tables below it with the user pages shared, None if there is
+// not enough memory for the tables. addr is where the memory the table maps starts, the pages in skip are not shared
+fn clone_table(table: PageTable, level: u32, addr: u64, skip: &Range<u64>) -> Option<PageTable> {
+    let res = try_alloc_page()? as PageTable;
+    unsafe {
+        write_bytes(res as *mut u8, 0, PAGE_SIZE as usize);
+    }
+
+    let entry_size = PAGE_SIZE << (9 * (2 - level));
+    for i in 0..PAGE_TABLE_SIZE {
+        let entry = *get_sub_page_table_entry(table, i);
+        let entry_addr = addr + i as u64 * entry_size;
+        if level < 2 && is_entry_table(entry) {
+            let Some(sub_table) = clone_table(get_entry_addr(entry).unwrap(), level + 1, entry_addr, skip) else {
+                destroy_page_table(res);
+                return None;
+            };
+            *get_sub_page_table_entry(res, i) = create_page_table_entry(sub_table as PhysAddr);
+        } else if level == 2 && (entry & PTE_USER) != 0 && !skip.contains(&entry_addr) {
+            // pages only the kernel can access like context pages are not cloned
+            *get_sub_page_table_entry(res, i) = share_entry(unsafe { AtomicU64::from_ptr(table.add(i)) });
+        }
+    }
+    Some(res)
+}
+
+// the user pages of src outside of skip are shared with dst, which has no user pages yet. the caller has to refresh
+// the paging of every thread using src, its writable pages are read-only now. returns false if there is not enough memory
+pub fn clone_user_pages(src: PageTable, dst: PageTable, skip: Range<u64>) -> bool {
+    for i in KERNEL_PT_ROOT_ENTRIES as usize..PAGE_TABLE_SIZE {
+        let entry = *get_sub_page_table_entry(src, i);
+        if is_entry_table(entry) {
+            let Some(table) = clone_table(get_entry_addr(entry).unwrap(), 1, i as u64 * (PAGE_SIZE << 18), &skip) else {
+                return false;
+            };
+            *get_sub_page_table_entry(dst, i) = create_page_table_entry(table as PhysAddr);
+        }
+    }
+    true
+}
+
+// true for a user page that has not been accessed yet
+pub fn is_page_reserved(page_table: PageTable, addr: u64) -> bool {
+    is_user_addr(addr) && find_page_table_entry_atomic(page_table, addr).is_some_and(|entry| {
+        let value = entry.load(Ordering::Acquire);
+        (value & (PTE_PRESENT | PTE_LAZY | PTE_USER)) == (PTE_LAZY | PTE_USER)
+    })
+}
+
+// removes a user page and frees its memory or drops its reservation, returns false if there is neither
+#[cfg(feature = "run_tests")]
+pub fn unmap_user_page(page_table: PageTable, addr: u64) -> bool {
+    let mut taken = Vec::new();
+    if !take_user_page(page_table, addr, &mut taken) {
+        return false;
+    }
+    refresh_paging();
+    for page in taken {
+        release_page(page);
+    }
+    true
+}
+
+// like unmap_user_page, but the memory of a mapped page is added to taken instead of freed, so other cores can flush
+// their tlb before it is released with release_page
+pub fn take_user_page(page_table: PageTable, addr: u64, taken: &mut Vec<PhysAddr>) -> bool {
+    if !is_user_addr(addr) {
+        return false;
+    }
+    let Some(entry) = find_page_table_entry_atomic(page_table, addr) else {
+        return false;
+    };
+
+    loop {
+        let value = entry.load(Ordering::Acquire);
+        if (value & PTE_USER) == 0 || (value & (PTE_PRESENT | PTE_LAZY)) == 0 {
+            return false;
+        }
+        if entry.compare_exchange(value, 0, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            if let Some(page) = get_entry_addr(value) {
+                taken.push(page as PhysAddr);
+            }
+            return true;
+        }
+    }
+}
+
+// returns the physical address behind a user address, None if the user is not allowed to access it
+pub fn user_virt_to_phys(page_table: PageTable, addr: u64, writable: bool) -> Option<PhysAddr> {
+    if !is_user_addr(addr) {
+        return None;
+    }
+
+    let entry = find_page_table_entry(page_table, addr)?;
+    if (entry & PTE_USER) == 0 || (writable && (entry & PTE_WRITE) == 0) {
+        return None;
+    }
+
+    Some(get_entry_addr(entry)? as PhysAddr + addr % PAGE_SIZE)
+}
+
+// also true for pages the user can not access
+pub fn is_page_mapped(page_table: PageTable, addr: u64) -> bool {
+    find_page_table_entry(page_table, addr).is_some()
+}
+
+fn count_leaf_pages(table: PageTable) -> u64 {
+    let mut res = 0;
+    for i in 0..PAGE_TABLE_SIZE {
+        let entry = *get_sub_page_table_entry(table, i);
+        if is_entry_table(entry) {
+            res += count_leaf_pages(get_entry_addr(entry).unwrap());
+        } else if is_entry_leaf(entry) {
+            res += 1;
+        }
+    }
+    res
+}
+
+// the pages mapped outside of the shared kernel part, the tables themselves are not counted
+pub fn count_user_pages(page_table: PageTable) -> u64 {
+    let mut res = 0;
+    for i in KERNEL_PT_ROOT_ENTRIES as usize..PAGE_TABLE_SIZE {
+        let entry = *get_sub_page_table_entry(page_table, i);
+        if is_entry_table(entry) {
+            res += count_leaf_pages(get_entry_addr(entry).unwrap());
+        }
+    }
+    res
+}
+
+// faults in the reserved pages of the range, false if any of it is not accessible by the user
+pub fn is_user_range(page_table: PageTable, addr: u64, size: usize, writable: bool) -> bool {
+    let Some(end) = addr.checked_add(size as u64) else {
+        return false;
+    };
+
+    // reserved pages are mapped, so they can be copied through their physical address
+    let access = if writable { PageAccess::Write } else { PageAccess::Read };
+    let mut page = addr / PAGE_SIZE * PAGE_SIZE;
+    while page < end {
+        if !fault_in_page(page_table, page, access) {
+            return false;
+        }
+        page += PAGE_SIZE;
+    }
+    true
+}
+
+// physical memory is identity mapped, so user pages can be accessed through their physical address
+fn copy_user_pages(page_table: PageTable, user_addr: u64, size: usize, writable: bool, copy_fn: &mut dyn FnMut(*mut u8, usize, usize)) -> bool {
+    if !is_user_range(page_table, user_addr, size, writable) {
+        return false;
+    }
+
+    let mut copied = 0;
+    while copied < size {
+        let addr = user_addr + copied as u64;
+        let this_size = min(size - copied, (PAGE_SIZE - addr % PAGE_SIZE) as usize);
+        let phys_addr = user_virt_to_phys(page_table, addr, writable).unwrap();
+        copy_fn(phys_addr as *mut u8, copied, this_size);
+        copied += this_size;
+    }
+    true
+}
+
+// returns false if the whole source range is not readable by the user
+pub fn copy_from_user(page_table: PageTable, src: u64, dst: &mut [u8]) -> bool {
+    copy_user_pages(page_table, src, dst.len(), false, &mut |user_ptr, offset, size| unsafe {
+        copy_nonoverlapping(user_ptr, dst.as_mut_ptr().add(offset), size);
+    })
+}
+
+// returns false if the whole destination range is not writable by the user
+pub fn copy_to_user(page_table: PageTable, dst: u64, src: &[u8]) -> bool {
+    copy_user_pages(page_table, dst, src.len(), true, &mut |user_ptr, offset, size| unsafe {
+        copy_nonoverlapping(src.as_ptr().add(offset), user_ptr, size);
+    })
+}
+
+// returns None if the string is not in user memory or is not valid utf-8
+pub fn copy_str_from_user(page_table: PageTable, src: u64, size: usize) -> Option<String> {
+    // checked before allocating, so a bogus size can not exhaust kernel memory
+    if !is_user_range(page_table, src, size, false) {
+        return None;
+    }
+
+    let mut data: Vec<u8> = Vec::new_with_size(size);
+    let data_slice = unsafe { core::slice::from_raw_parts_mut(data.as_mut_ptr(), size) };
+    if !copy_from_user(page_table, src, data_slice) {
+        return None;
+    }
+
+    let s = core::str::from_utf8(data_slice).ok()?;
+    Some(String::from(s))
+}
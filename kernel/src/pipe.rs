@@ -0,0 +1,176 @@
+use core::cmp::min;
+use kernel_std::{Box, Mutable, Vec};
+use crate::memory::{copy_from_user, copy_to_user, is_user_range, PageTable};
+use crate::wait_queue::WaitQueue;
+
+const PIPE_SIZE: usize = 4096;
+const MAX_PIPES: usize = 64;
+
+struct Pipe {
+    buffer: Box<[u8; PIPE_SIZE]>,
+    read_pos: usize,
+    size: usize,
+    readers: usize, // how many read ends are open
+    writers: usize, // how many write ends are open
+}
+
+static PIPES: Mutable<[Option<Pipe>; MAX_PIPES]> = Mutable::new([const { None }; MAX_PIPES]);
+
+// pipes are used under the lock of the file descriptors, so the waiters are woken later
+static READ_QUEUES: [WaitQueue; MAX_PIPES] = [const { WaitQueue::new() }; MAX_PIPES];
+static WRITE_QUEUES: [WaitQueue; MAX_PIPES] = [const { WaitQueue::new() }; MAX_PIPES];
+
+#[derive(Clone, Copy)]
+pub enum PipeStatus {
+    Done(usize),
+    Blocked, // the pipe is empty when reading or full when writing
+    Failed,
+}
+
+// returns the id of a new pipe with one read end and one write end
+pub fn create_pipe() -> Option<usize> {
+    let t = PIPES.borrow();
+    let pipes = PIPES.get_mut(&t);
+    let res = (0..MAX_PIPES).find(|&id| pipes[id].is_none());
+    if let Some(id) = res {
+        pipes[id] = Some(Pipe {
+            buffer: Box::new([0; PIPE_SIZE]),
+            read_pos: 0,
+            size: 0,
+            readers: 1,
+            writers: 1,
+        });
+    }
+    PIPES.release(t);
+    res
+}
+
+fn with_pipe<T>(id: usize, f: &mut dyn FnMut(&mut Pipe) -> T) -> T {
+    let t = PIPES.borrow();
+    let res = f(PIPES.get_mut(&t)[id].as_mut().unwrap());
+    PIPES.release(t);
+    res
+}
+
+pub fn add_pipe_reader(id: usize) {
+    with_pipe(id, &mut |pipe| pipe.readers += 1);
+}
+
+pub fn add_pipe_writer(id: usize) {
+    with_pipe(id, &mut |pipe| pipe.writers += 1);
+}
+
+fn free_pipe_if_unused(id: usize) {
+    let t = PIPES.borrow();
+    let pipes = PIPES.get_mut(&t);
+    if let Some(pipe) = &pipes[id] {
+        if pipe.readers == 0 && pipe.writers == 0 {
+            pipes[id] = None;
+        }
+    }
+    PIPES.release(t);
+}
+
+pub fn close_pipe_reader(id: usize) {
+    with_pipe(id, &mut |pipe| pipe.readers -= 1);
+    WRITE_QUEUES[id].wake_all_later();
+    free_pipe_if_unused(id);
+}
+
+pub fn close_pipe_writer(id: usize) {
+    with_pipe(id, &mut |pipe| pipe.writers -= 1);
+    READ_QUEUES[id].wake_all_later();
+    free_pipe_if_unused(id);
+}
+
+// a reader can continue if there is data or nobody can write anymore
+fn can_read_pipe(id: usize) -> bool {
+    with_pipe(id, &mut |pipe| pipe.size > 0 || pipe.writers == 0)
+}
+
+// a writer can continue if there is space or nobody can read anymore
+fn can_write_pipe(id: usize) -> bool {
+    with_pipe(id, &mut |pipe| pipe.size < PIPE_SIZE || pipe.readers == 0)
+}
+
+pub fn wait_for_pipe_read(pid: usize, id: usize) {
+    READ_QUEUES[id].wait_until(pid, &|| can_read_pipe(id));
+}
+
+pub fn wait_for_pipe_write(pid: usize, id: usize) {
+    WRITE_QUEUES[id].wait_until(pid, &|| can_write_pipe(id));
+}
+
+// reads as much as is available into user memory, 0 means all write ends are closed
+pub fn read_pipe(id: usize, page_table: PageTable, buf: u64, size: usize) -> PipeStatus {
+    // the data is only taken out of the pipe once it is known to fit, and copied without holding the lock
+    let size = min(size, PIPE_SIZE);
+    if !is_user_range(page_table, buf, size, true) {
+        return PipeStatus::Failed;
+    }
+
+    let mut data = Vec::new();
+    let res = with_pipe(id, &mut |pipe| {
+        if size == 0 {
+            return PipeStatus::Done(0);
+        }
+
+        if pipe.size == 0 {
+            return if pipe.writers == 0 { PipeStatus::Done(0) } else { PipeStatus::Blocked };
+        }
+
+        let size = min(size, pipe.size);
+        for i in 0..size {
+            // the data may wrap around the end of the buffer
+            data.push(pipe.buffer[(pipe.read_pos + i) % PIPE_SIZE]);
+        }
+        pipe.read_pos = (pipe.read_pos + size) % PIPE_SIZE;
+        pipe.size -= size;
+        PipeStatus::Done(size)
+    });
+
+    if let PipeStatus::Done(1..) = res {
+        WRITE_QUEUES[id].wake_all_later();
+        let data_slice = unsafe { core::slice::from_raw_parts(data.as_ptr(), data.size()) };
+        if !copy_to_user(page_table, buf, data_slice) {
+            return PipeStatus::Failed;
+        }
+    }
+    res
+}
+
+// writes as much as fits from user memory
+pub fn write_pipe(id: usize, page_table: PageTable, buf: u64, size: usize) -> PipeStatus {
+    let size = min(size, PIPE_SIZE);
+    let mut data = Vec::new_with_size(size);
+    let data_slice = unsafe { core::slice::from_raw_parts_mut(data.as_mut_ptr(), size) };
+    if !copy_from_user(page_table, buf, data_slice) {
+        return PipeStatus::Failed;
+    }
+
+    let res = with_pipe(id, &mut |pipe| {
+        if pipe.readers == 0 {
+            return PipeStatus::Failed;
+        }
+
+        if size == 0 {
+            return PipeStatus::Done(0);
+        }
+
+        if pipe.size == PIPE_SIZE {
+            return PipeStatus::Blocked;
+        }
+
+        let size = min(size, PIPE_SIZE - pipe.size);
+        for (i, byte) in data_slice.iter().take(size).enumerate() {
+            pipe.buffer[(pipe.read_pos + pipe.size + i) % PIPE_SIZE] = *byte;
+        }
+        pipe.size += size;
+        PipeStatus::Done(size)
+    });
+
+    if let PipeStatus::Done(1..) = res {
+        READ_QUEUES[id].wake_all_later();
+    }
+    res
+}
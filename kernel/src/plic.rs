@@ -1,10 +1,11 @@
+use crate::device_tree::get_machine;
 use crate::riscv::get_core_id;
 
-const PLIC_BASE: u64 = 0x0c000000;
-
+// only the interrupts of virtio devices are used
 pub fn plicinit() {
-    for irq in 0..=8 {
-        let addr = (PLIC_BASE + 4 * irq) as *mut u32;
+    let machine = get_machine();
+    for slot in &machine.virtio_slots[..machine.num_virtio_slots] {
+        let addr = (machine.plic_base + 4 * slot.irq as u64) as *mut u32;
         unsafe {
             addr.write_volatile(1);
         }
@@ -12,26 +13,28 @@ pub fn plicinit() {
 }
 
 pub fn plicinithart() {
-    let val = 0b111111111;
-    let addr1 = (PLIC_BASE + 0x2080 + get_core_id() * 0x100) as *mut u32;
-    unsafe {
-        addr1.write_volatile(val);
+    let machine = get_machine();
+    for slot in &machine.virtio_slots[..machine.num_virtio_slots] {
+        let addr1 = (machine.plic_base + 0x2080 + get_core_id() * 0x100 + 4 * (slot.irq as u64 / 32)) as *mut u32;
+        unsafe {
+            addr1.write_volatile(addr1.read_volatile() | 1 << (slot.irq % 32));
+        }
     }
 
-    let addr2 = (PLIC_BASE + 0x201000 + get_core_id() * 0x2000) as *mut u32;
+    let addr2 = (get_machine().plic_base + 0x201000 + get_core_id() * 0x2000) as *mut u32;
     unsafe {
         addr2.write_volatile(0);
     }
 }
 
 pub fn plic_irq() -> u32 {
-    let addr = (PLIC_BASE + 0x201004 + get_core_id() * 0x2000) as *mut u32;
+    let addr = (get_machine().plic_base + 0x201004 + get_core_id() * 0x2000) as *mut u32;
     unsafe { addr.read_volatile() }
 }
 
 pub fn plic_complete(irq: u32) {
-    let addr = (PLIC_BASE + 0x201004 + get_core_id() * 0x2000) as *mut u32;
+    let addr = (get_machine().plic_base + 0x201004 + get_core_id() * 0x2000) as *mut u32;
     unsafe {
         addr.write_volatile(irq);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,93 @@
+#[cfg(not(feature = "sbi"))]
+use core::ptr::write_volatile;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+use kernel_std::{println, Lock};
+use crate::boot::{get_num_cores, infinite_loop};
+#[cfg(not(feature = "sbi"))]
+use crate::device_tree::get_machine;
+use crate::disk::memory_disk::unmount_disk;
+use crate::riscv::interrupts_enable;
+use crate::timer::Instant;
+#[cfg(feature = "sbi")]
+use crate::sbi::{sbi_system_reset, RESET_REASON_FAILURE, RESET_REASON_NONE, RESET_TYPE_COLD_REBOOT, RESET_TYPE_SHUTDOWN};
+
+// what the sifive test device does with the value written to it, qemu exits with the upper 16 bits on a failure
+const TEST_FAIL: u32 = 0x3333;
+const TEST_PASS: u32 = 0x5555;
+const TEST_RESET: u32 = 0x7777;
+
+// taken by the first core that powers off, it is never released
+static POWER_LOCK: Lock = Lock::new();
+// set when the machine is about to turn off, the other cores stop when they next get to the scheduler or idle
+static HALTED: AtomicBool = AtomicBool::new(false);
+static NUM_PARKED: AtomicUsize = AtomicUsize::new(0);
+// a core stuck outside the scheduler does not keep the machine from turning off
+const PARK_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn park() -> ! {
+    interrupts_enable(false);
+    NUM_PARKED.fetch_add(1, Ordering::Release);
+    infinite_loop();
+}
+
+// called without any locks held, so a halted core never leaves a lock or a write to the disk unfinished
+pub fn park_if_halted() {
+    if HALTED.load(Ordering::Acquire) {
+        park();
+    }
+}
+
+#[cfg(not(feature = "sbi"))]
+fn write_power_value(value: u32) {
+    unsafe {
+        write_volatile(get_machine().test_base as *mut u32, value);
+    }
+}
+
+// the firmware owns the test device, its system reset only tells a failure apart and loses the status
+#[cfg(feature = "sbi")]
+fn write_power_value(value: u32) {
+    match value & 0xFFFF {
+        TEST_PASS => sbi_system_reset(RESET_TYPE_SHUTDOWN, RESET_REASON_NONE),
+        TEST_RESET => sbi_system_reset(RESET_TYPE_COLD_REBOOT, RESET_REASON_NONE),
+        _ => sbi_system_reset(RESET_TYPE_SHUTDOWN, RESET_REASON_FAILURE),
+    }
+}
+
+// the other cores are stopped first, then the cached sectors of the root disk are written back before the machine goes away
+fn power_off(value: u32) -> ! {
+    if !POWER_LOCK.try_lock() {
+        park();
+    }
+
+    HALTED.store(true, Ordering::Release);
+    let start = Instant::now();
+    while NUM_PARKED.load(Ordering::Acquire) < get_num_cores() - 1 && start.elapsed() < PARK_TIMEOUT {
+        spin_loop();
+    }
+
+    unmount_disk();
+    write_power_value(value);
+
+    // there is no test device, so all that is left is to wait
+    println!("The machine can be turned off now");
+    infinite_loop();
+}
+
+// qemu exits with the status, only its lower 16 bits are kept
+pub fn shutdown(status: u32) -> ! {
+    println!("Shutting down");
+    if status == 0 {
+        power_off(TEST_PASS);
+    } else {
+        power_off((status << 16) | TEST_FAIL);
+    }
+}
+
+// the machine starts again from the firmware, qemu loads the kernel image again
+pub fn reboot() -> ! {
+    println!("Rebooting");
+    power_off(TEST_RESET);
+}
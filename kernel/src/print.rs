@@ -2,10 +2,31 @@ use core::fmt;
 use core::fmt::Write;
 use kernel_std::{debug_str, Mutable};
 use log::debug;
+#[cfg(not(feature = "sbi"))]
+use crate::device_tree::get_machine;
+#[cfg(feature = "sbi")]
+use crate::sbi::sbi_console_putchar;
+use crate::boot::get_boot_hart;
 use crate::riscv::{get_core_id, interrupts_get};
 use crate::text_renderer::{get_screen_height_chars, get_screen_width_chars, render_text_to_screen, scroll, set_char, TextColor};
 use crate::timer::get_ticks;
 
+// the serial console, it waits until the uart can take the character
+#[cfg(not(feature = "sbi"))]
+fn console_putchar(c: u8) {
+    let addr = get_machine().uart_base as *mut u8;
+    unsafe {
+        while addr.add(5).read_volatile() & (1 << 5) == 0 {}
+        addr.write_volatile(c);
+    }
+}
+
+// the firmware writes to the serial console
+#[cfg(feature = "sbi")]
+fn console_putchar(c: u8) {
+    sbi_console_putchar(c);
+}
+
 struct Writer {
     x: usize,
     text_color: TextColor,
@@ -32,11 +53,7 @@ impl Writer {
     }
 
     fn write_byte(&mut self, c: u8) {
-        let addr = 0x10000000 as *mut u8;
-        unsafe {
-            while addr.add(5).read_volatile() & (1 << 5) == 0 {}
-            addr.write_volatile(c);
-        }
+        console_putchar(c);
 
         if c == b'\n' {
             self.new_line();
@@ -82,7 +99,7 @@ pub fn _print(args: fmt::Arguments) {
 const PRINT_REFRESH_INTERVAL: u64 = 16;
 
 pub fn check_screen_refresh_for_print() {
-    if get_core_id() != 0 || !interrupts_get() {
+    if get_core_id() != get_boot_hart() || !interrupts_get() {
         return;
     }
 
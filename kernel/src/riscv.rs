@@ -89,6 +89,9 @@ csr_get_set!(mideleg);
 pub const SIE_EXTERNAL: u64 = 1 << 9;
 pub const SIE_TIMER: u64 = 1 << 5;
 pub const SIE_SOFTWARE: u64 = 1 << 1;
+// Supervisor Interrupt Pending
+pub const SIP_TIMER: u64 = 1 << 5;
+pub const SIP_SOFTWARE: u64 = 1 << 1;
 csr_get_set!(sie);
 
 // Machine Interrupt Enable
@@ -130,9 +133,6 @@ pub unsafe fn amoswap(addr: *mut i32, val: i32) -> i32 {
     res
 }
 
-// core local interrupter for the timer
-pub const CLINT: u64 = 0x2000000;
-
 csr_get_set!(mscratch);
 
 // holds the trap handler address for machine mode
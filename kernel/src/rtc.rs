@@ -0,0 +1,28 @@
+use core::ptr::read_volatile;
+use kernel_std::{DateTime, Lock};
+use crate::device_tree::get_machine;
+
+// the goldfish real time clock of the qemu virt machine counts nanoseconds since 1970-01-01 UTC
+const RTC_TIME_LOW: u64 = 0x00;
+const RTC_TIME_HIGH: u64 = 0x04;
+
+// reading the low half latches the high half, so two cores must not read at the same time
+static RTC_LOCK: Lock = Lock::new();
+
+// nanoseconds since 1970-01-01 00:00:00 UTC
+pub fn get_unix_time_ns() -> u64 {
+    RTC_LOCK.spinlock();
+    let low = unsafe { read_volatile((get_machine().rtc_base + RTC_TIME_LOW) as *const u32) };
+    let high = unsafe { read_volatile((get_machine().rtc_base + RTC_TIME_HIGH) as *const u32) };
+    RTC_LOCK.unlock();
+    ((high as u64) << 32) | low as u64
+}
+
+// whole seconds since 1970-01-01 00:00:00 UTC, what gets stored as a timestamp
+pub fn get_unix_time() -> u64 {
+    get_unix_time_ns() / 1_000_000_000
+}
+
+pub fn get_date_time() -> DateTime {
+    DateTime::from_unix_secs(get_unix_time())
+}
@@ -0,0 +1,55 @@
+use core::arch::asm;
+
+// the supervisor binary interface of the firmware, a7 selects the extension and a6 the function
+const EXT_LEGACY_CONSOLE_PUTCHAR: u64 = 0x01;
+const EXT_TIME: u64 = 0x54494D45;
+const EXT_IPI: u64 = 0x735049;
+const EXT_HSM: u64 = 0x48534D;
+const EXT_SYSTEM_RESET: u64 = 0x53525354;
+const EXT_DEBUG_CONSOLE: u64 = 0x4442434E;
+
+pub const RESET_TYPE_SHUTDOWN: u64 = 0;
+pub const RESET_TYPE_COLD_REBOOT: u64 = 1;
+pub const RESET_REASON_NONE: u64 = 0;
+pub const RESET_REASON_FAILURE: u64 = 1;
+
+// None if the firmware returned an error
+fn sbi_call(extension: u64, function: u64, arg0: u64, arg1: u64, arg2: u64) -> Option<u64> {
+    let error: i64;
+    let value: u64;
+    unsafe {
+        asm!("ecall", inlateout("a0") arg0 => error, inlateout("a1") arg1 => value, in("a2") arg2, in("a6") function, in("a7") extension);
+    }
+    if error == 0 {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+// the supervisor timer interrupt is pending from time on, setting the next one clears it
+pub fn sbi_set_timer(time: u64) {
+    sbi_call(EXT_TIME, 0, time, 0, 0);
+}
+
+// a supervisor software interrupt for every hart in the mask, bit i is hart base + i
+pub fn sbi_send_ipi(hart_mask: u64, hart_mask_base: u64) -> bool {
+    sbi_call(EXT_IPI, 0, hart_mask, hart_mask_base, 0).is_some()
+}
+
+// the hart starts in supervisor mode at start with its id in a0 and opaque in a1
+pub fn sbi_hart_start(hart: u64, start: u64, opaque: u64) -> bool {
+    sbi_call(EXT_HSM, 0, hart, start, opaque).is_some()
+}
+
+// only returns if the firmware can not do it
+pub fn sbi_system_reset(reset_type: u64, reason: u64) {
+    sbi_call(EXT_SYSTEM_RESET, 0, reset_type, reason, 0);
+}
+
+// older firmware does not have the debug console, its legacy call does the same
+pub fn sbi_console_putchar(c: u8) {
+    if sbi_call(EXT_DEBUG_CONSOLE, 2, c as u64, 0, 0).is_none() {
+        sbi_call(EXT_LEGACY_CONSOLE_PUTCHAR, 0, c as u64, 0, 0);
+    }
+}
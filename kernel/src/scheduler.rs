@@ -1,14 +1,23 @@
 use core::arch::asm;
-use core::ptr::{copy, write_bytes};
-use kernel_std::{debug, debugln, println, Lock, Mutable, String, Vec};
-use crate::boot::NUM_CORES;
+use core::ptr::{addr_of, addr_of_mut, copy, write_bytes};
+use core::cmp::{max, min};
+use core::hint::spin_loop;
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+use kernel_std::{debug, debugln, serialize, Box, Lock, Mutable, String, Vec};
+use crate::boot::get_num_cores;
 use crate::disk::filesystem::read_file;
 use crate::elf::{verify_elf_header, ElfHeader, ElfProgramHeader};
-use crate::memory::{create_page_table, clear_page_table, map_page_auto, switch_to_page_table, PageTable, VirtAddr, KERNEL_VIRTUAL_TOP, PAGE_SIZE, USER_CONTEXT, USER_STACK, USER_STACK_SIZE, refresh_paging, virt_to_phys};
+use crate::fd_table::FdTable;
+use crate::keyboard::release_foreground_process;
+use crate::memory::{take_user_page, take_replaced_pages, release_page, PhysAddr, count_user_pages, create_page_table, clear_page_table, get_num_free_pages, map_page_auto, reserve_page, unmap_page, clone_user_pages, switch_to_page_table, get_current_page_table, PageTable, VirtAddr, KERNEL_VIRTUAL_TOP, PAGE_SIZE, USER_ARGS, USER_ARGS_SIZE, USER_CONTEXT, USER_STACK_END, USER_STACK_MAX_SIZE, USER_STACK_SIZE, USER_THREADS, DEFAULT_STACK_LIMIT, is_page_mapped, is_page_reserved, USER_THREADS_END, USER_THREAD_SIZE, USER_VIRTUAL_END, refresh_paging, virt_to_phys};
+use crate::power::park_if_halted;
 use crate::print::check_screen_refresh_for_print;
+#[cfg(feature = "sbi")]
+use crate::sbi::sbi_send_ipi;
 use crate::riscv::{get_core_id, get_sstatus, interrupts_enable, set_sstatus, SSTATUS_SPP, SSTATUS_UIE};
-use crate::timer::get_ticks;
+use crate::timer::{get_ticks, wake_sleepers};
 use crate::trap::switch_to_user_trap;
+use crate::wait_queue::{wake_deferred, WaitQueue};
 
 #[derive(Debug)]
 #[repr(C)]
@@ -51,21 +60,58 @@ pub struct Context {
 #[derive(Clone, Copy)]
 pub struct CpuData {
     pub was_last_interrupt_external: bool,
-    pub curr_pid: usize,
     pub last_pid: usize,
+    pub run_start: u64, // ticks when the last process started running
+    pub context: u64, // where the context of the last process is mapped
+    pub idle_ticks: u64, // how long the core has been waiting for an interrupt
+    pub is_idle: bool, // waiting in wfi with nothing to run
 }
 
-static mut CPU_DATA: [CpuData; NUM_CORES] = [CpuData { was_last_interrupt_external: false, curr_pid: 1000, last_pid: 1000 }; NUM_CORES];
+// one for every core, created with the run queues
+static mut CPU_DATA: Option<Vec<CpuData>> = None;
 
 pub fn get_cpu_data() -> &'static mut CpuData {
     unsafe {
-        &mut CPU_DATA[get_core_id() as usize]
+        &mut (*addr_of_mut!(CPU_DATA)).as_mut().unwrap()[get_core_id() as usize]
     }
 }
 
+// waits for the next interrupt, the time until then counts as idle time of the core
+pub fn idle() {
+    park_if_halted();
+    let start = get_ticks();
+    get_cpu_data().is_idle = true;
+    unsafe {
+        asm!("wfi");
+    }
+    get_cpu_data().is_idle = false;
+    get_cpu_data().idle_ticks += get_ticks() - start;
+}
+
+// an idle core does not have to wait for its next timer interrupt to run a process that was queued for it
+#[cfg(feature = "sbi")]
+fn wake_core(core: usize) {
+    let idle = unsafe { (*addr_of!(CPU_DATA)).as_ref().unwrap()[core].is_idle };
+    if idle && core as u64 != get_core_id() {
+        sbi_send_ipi(1, core as u64);
+    }
+}
+
+// every core gets the software interrupt of timervec anyway
+#[cfg(not(feature = "sbi"))]
+const fn wake_core(_core: usize) {}
+
+pub fn get_idle_ticks() -> Vec<u64> {
+    let mut res = Vec::new();
+    for core in 0..get_num_cores() {
+        res.push(unsafe { (*addr_of!(CPU_DATA)).as_ref().unwrap()[core].idle_ticks });
+    }
+    res
+}
+
 pub fn get_context() -> &'static mut Context {
     unsafe {
-        &mut *(USER_CONTEXT as *mut Context)
+        &mut *(get_cpu_data().context as *mut Context)
     }
 }
 
@@ -74,129 +120,629 @@ enum ProcessState {
     Loading, // Its initial phase, loading the program into memory
     Ready, // Ready to be run by a core
     Running, // Is already running on a core
-    Sleeping(u64), // Ignore until get_ticks() is greater or equal than the value
+    Blocked(usize), // Ignore until woken up through the wait queue, sleep list or futex with this address
+    Exiting(i32), // The main thread has exited with this code, waiting for the other threads to be released
+    Zombie(i32), // Has exited with this code, waiting for the parent to collect it
 }
 
 pub struct Process {
     state: ProcessState,
-    needs_paging_refresh: [bool; NUM_CORES],
+    needs_paging_refresh: u64, // a bit for every core
+    parent_pid: Option<usize>, // None if the process was started by the kernel or its parent has exited
+    fd_table: FdTable,
+    path: String,
+    start_tick: u64,
+    cpu_ticks: u64, // how long the process has been running
+    killed: bool, // terminated by the scheduler as soon as it is not running
+    thread_of: Option<usize>, // the pid of the main thread if this is another thread, its page table and files are used
+    context: u64, // where the context page is mapped
+    nice: i32, // from MIN_NICE (highest priority) to MAX_NICE
+    vruntime: u64, // cpu time scaled by the weight of the nice value, the lowest one runs next
+    affinity: u64, // the cores it may run on, bit i is core i
+    last_core: usize, // it is queued there again if that core is not busier than the others
+    stack_limit: u64, // how far the stack of the main thread may grow down from USER_STACK_END, used on the main thread
+    started_by_kernel: bool, // only these may turn off the machine, a forked child keeps it, used on the main thread
+}
+
+impl ProcessState {
+    const fn name(&self) -> &'static str {
+        match self {
+            Self::Loading => "loading",
+            Self::Ready => "ready",
+            Self::Running => "running",
+            Self::Blocked(_) => "blocked",
+            Self::Exiting(_) => "exiting",
+            Self::Zombie(_) => "zombie",
+        }
+    }
+}
+
+pub struct ProcessInfo {
+    pub pid: usize,
+    pub parent_pid: Option<usize>,
+    pub state: &'static str,
+    pub nice: i32,
+    pub path: String,
+    pub start_tick: u64,
+    pub cpu_ticks: u64,
+    pub core: usize, // the core it is running on or ran on last
+    pub memory_pages: Option<u64>, // None while loading and for threads other than the main one, they share its memory
+}
+
+pub const MIN_NICE: i32 = -20;
+pub const MAX_NICE: i32 = 19;
+
+// every nice level gets about 10% less cpu time than the one before, 1024 is nice 0
+const NICE_WEIGHTS: [u64; (MAX_NICE - MIN_NICE + 1) as usize] = [
+    88761, 71755, 56483, 46273, 36291,
+    29154, 23254, 18705, 14949, 11916,
+    9548, 7620, 6100, 4904, 3906,
+    3121, 2501, 1991, 1586, 1277,
+    1024, 820, 655, 526, 423,
+    335, 272, 215, 172, 137,
+    110, 87, 70, 56, 45,
+    36, 29, 23, 18, 15,
+];
+
+// how much vruntime a tick of a nice 0 process adds
+const VRUNTIME_PER_TICK: u64 = 1024 * 1024;
+
+// follows the vruntime of the processes being picked, new and woken up processes start from here.
+// every core picks processes, so it only ever moves forward atomically
+static MIN_VRUNTIME: AtomicU64 = AtomicU64::new(0);
+
+// the affinity mask that allows every core, all 64 bits are set with MAX_CORES cores
+pub fn get_all_cores() -> u64 {
+    u64::MAX >> (64 - get_num_cores())
+}
+
+pub const fn get_nice_weight(nice: i32) -> u64 {
+    NICE_WEIGHTS[(nice - MIN_NICE) as usize]
+}
+
+// the exit code the parent sees when a process was killed
+pub const KILLED_EXIT_CODE: i32 = -1;
+
+struct ProcSlot {
+    process: Option<Process>,
+    page_table: PageTable,
+    lock: Lock,
+    shared_lock: Lock, // guards what the threads of a process share (page table and file descriptors), used on the main thread
+    exit_queue: WaitQueue, // woken up when the process in this slot is released
 }
 
-const NUM_PROC: usize = 16;
-static mut PROCTABLE: [(Option<Process>, PageTable); NUM_PROC] = [const { (None, 0 as PageTable) }; NUM_PROC];
+impl ProcSlot {
+    const fn new() -> Self {
+        Self { process: None, page_table: 0 as PageTable, lock: Lock::new(), shared_lock: Lock::new(), exit_queue: WaitQueue::new() }
+    }
+}
+
+// the table grows a chunk at a time, chunks never move so slots can be used without the alloc lock
+const PROCTABLE_CHUNK_SIZE: usize = 16;
+const MAX_PROCTABLE_CHUNKS: usize = 256;
+static mut PROCTABLE: [Option<Box<[ProcSlot; PROCTABLE_CHUNK_SIZE]>>; MAX_PROCTABLE_CHUNKS] = [const { None }; MAX_PROCTABLE_CHUNKS];
+static mut NUM_SLOTS: usize = 0;
 static PROCTABLE_ALLOC_LOCK: Lock = Lock::new();
-static PROCTABLE_LOCKS: [Lock; NUM_PROC] = [const { Lock::new() }; NUM_PROC];
 
-pub fn init_scheduler() {
+// the lock of the slot has to be held to use the process
+unsafe fn get_slot(pid: usize) -> &'static mut ProcSlot {
+    &mut PROCTABLE[pid / PROCTABLE_CHUNK_SIZE].as_mut().unwrap()[pid % PROCTABLE_CHUNK_SIZE]
+}
+
+fn get_lock(pid: usize) -> &'static Lock {
+    unsafe { &get_slot(pid).lock }
+}
+
+fn get_shared_lock(pid: usize) -> &'static Lock {
+    unsafe { &get_slot(pid).shared_lock }
+}
+
+fn get_num_slots() -> usize {
+    fence(Ordering::Acquire);
+    unsafe { NUM_SLOTS }
+}
+
+// the alloc lock has to be held, returns false if there is not enough memory
+fn grow_proctable() -> bool {
+    let num_slots = get_num_slots();
+    if num_slots == MAX_PROCTABLE_CHUNKS * PROCTABLE_CHUNK_SIZE || get_num_free_pages() < 2 * PROCTABLE_CHUNK_SIZE as u64 {
+        return false;
+    }
+
+    let mut chunk = Box::new([const { ProcSlot::new() }; PROCTABLE_CHUNK_SIZE]);
+    for slot in chunk.iter_mut() {
+        slot.page_table = create_page_table();
+    }
+
     unsafe {
-        for i in 0..NUM_PROC {
-            PROCTABLE[i].1 = create_page_table();
-        }
+        PROCTABLE[num_slots / PROCTABLE_CHUNK_SIZE] = Some(chunk);
+        // the chunk has to be visible before other cores can index it
+        fence(Ordering::Release);
+        NUM_SLOTS = num_slots + PROCTABLE_CHUNK_SIZE;
     }
+    true
 }
 
-fn get_free_proc() -> usize {
+pub fn init_scheduler() {
+    PROCTABLE_ALLOC_LOCK.spinlock();
+    assert!(grow_proctable());
+    PROCTABLE_ALLOC_LOCK.unlock();
+
+    let mut cpu_data = Vec::new();
+    let mut run_queues = Vec::new();
+    for _ in 0..get_num_cores() {
+        cpu_data.push(CpuData { was_last_interrupt_external: false, last_pid: 1000, run_start: 0, context: USER_CONTEXT, idle_ticks: 0, is_idle: false });
+        run_queues.push(Mutable::new(Vec::new()));
+    }
     unsafe {
-        for i in 0..NUM_PROC {
-            if PROCTABLE[i].0.is_none() {
-                return i;
-            }
+        CPU_DATA = Some(cpu_data);
+        RUN_QUEUES = Some(run_queues);
+    }
+}
+
+// the alloc lock has to be held
+fn get_free_proc() -> Option<usize> {
+    let num_slots = get_num_slots();
+    for i in 0..num_slots {
+        if unsafe { get_slot(i).process.is_none() } {
+            return Some(i);
         }
     }
 
-    panic!("No free process slots");
+    if grow_proctable() {
+        Some(num_slots)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RunProgramError {
+    FileNotFound,
+    InvalidElf,
+    ArgumentsTooLarge,
+    NoMemory, // not enough free pages for the program or the process table can not grow
+}
+
+// a segment has to come from the file and land in user memory above the thread stacks
+fn verify_program_header(header: &ElfProgramHeader, program_size: usize) -> bool {
+    let file_end = header.offset.checked_add(header.file_size);
+    let memory_end = header.vaddr.checked_add(header.memory_size);
+    header.memory_size >= header.file_size
+        && file_end.is_some_and(|end| end <= program_size as u64)
+        && header.vaddr >= USER_THREADS_END
+        && memory_end.is_some_and(|end| end <= USER_VIRTUAL_END)
 }
 
-pub fn run_program(path: &String) {
-    let program = read_file(path).unwrap();
+// checks the file and returns its elf header and the segments to load
+fn parse_program(program: &Vec<u8>) -> Result<(ElfHeader, Vec<ElfProgramHeader>), RunProgramError> {
+    if program.size() < size_of::<ElfHeader>() {
+        return Err(RunProgramError::InvalidElf);
+    }
 
     let elf_header = unsafe { (program.as_ptr() as *const ElfHeader).read() };
 
     if !verify_elf_header(&elf_header) {
-        println!("Invalid ELF header");
-        return;
+        return Err(RunProgramError::InvalidElf);
+    }
+
+    let ph_end = elf_header.ph_offset.checked_add(elf_header.ph_entry_count as u64 * size_of::<ElfProgramHeader>() as u64);
+    if ph_end.is_none_or(|end| end > program.size() as u64) {
+        return Err(RunProgramError::InvalidElf);
+    }
+
+    // only loadable segments that take up memory are kept
+    let mut segments = Vec::new();
+    for i in 0..elf_header.ph_entry_count {
+        let program_header = unsafe { (program.as_ptr().add(elf_header.ph_offset as usize) as *const ElfProgramHeader).add(i as usize).read() };
+        if program_header.p_type == 1 && program_header.memory_size != 0 {
+            if !verify_program_header(&program_header, program.size()) {
+                return Err(RunProgramError::InvalidElf);
+            }
+            segments.push(program_header);
+        }
+    }
+
+    Ok((elf_header, segments))
+}
+
+// maps the segments into the current page table and copies their data from the file
+fn load_segments(program: &Vec<u8>, segments: &Vec<ElfProgramHeader>) {
+    for header in segments {
+        #[cfg(feature = "assertions")]
+        assert!(header.vaddr >= KERNEL_VIRTUAL_TOP);
+
+        // only the pages with data from the file are mapped now, the rest of the bss when it is first accessed
+        let low_page = header.vaddr / PAGE_SIZE;
+        let file_page = (header.vaddr + header.file_size).div_ceil(PAGE_SIZE);
+        let high_page = (header.vaddr + header.memory_size).div_ceil(PAGE_SIZE);
+        for page in low_page..file_page {
+            map_page_auto((page * PAGE_SIZE) as VirtAddr, true, true, true, true);
+        }
+        for page in file_page..high_page {
+            reserve_page((page * PAGE_SIZE) as VirtAddr, true, true, true);
+        }
+
+        #[cfg(feature = "assertions")]
+        assert!(header.memory_size >= header.file_size);
+        let ptr_low = header.vaddr as *mut u8;
+        let ptr_mid = (header.vaddr + header.file_size) as *mut u8;
+        let mapped_end = min(header.vaddr + header.memory_size, file_page * PAGE_SIZE);
+        unsafe {
+            copy(program.as_ptr().add(header.offset as usize), ptr_low, header.file_size as usize);
+            write_bytes(ptr_mid, 0, (mapped_end - (header.vaddr + header.file_size)) as usize);
+        }
+    }
+}
+
+// reserves the stack and maps the arguments and a context that starts at entry in the current page table
+fn setup_stack_and_args(args_data: &Vec<u8>, entry: u64) {
+    #[cfg(feature = "assertions")]
+    assert_eq!(USER_STACK_SIZE % PAGE_SIZE, 0);
+    let stack_pages = USER_STACK_SIZE / PAGE_SIZE;
+    let stack_top = USER_STACK_END;
+    for i in 1..=stack_pages {
+        reserve_page((stack_top - i * PAGE_SIZE) as VirtAddr, true, true, false);
+    }
+
+    for i in 0..USER_ARGS_SIZE / PAGE_SIZE {
+        map_page_auto((USER_ARGS + i * PAGE_SIZE) as VirtAddr, true, true, true, false);
+    }
+    unsafe {
+        (USER_ARGS as *mut u64).write(args_data.size() as u64);
+        copy(args_data.as_ptr(), (USER_ARGS + 8) as *mut u8, args_data.size());
     }
 
+    map_page_auto(USER_CONTEXT as VirtAddr, true, true, false, false);
+    let context = unsafe {
+        write_bytes(USER_CONTEXT as *mut u8, 0, size_of::<Context>());
+        &mut *(USER_CONTEXT as *mut Context)
+    };
+
+    context.pc = entry;
+    context.sp = stack_top;
+    context.a0 = USER_ARGS;
+}
+
+// returns the pid of the new process
+pub fn run_program(path: &String, args: &Vec<String>, env: &Vec<(String, String)>, parent_pid: Option<usize>, fd_table: FdTable) -> Result<usize, RunProgramError> {
+    // the program gets the arguments and environment at USER_ARGS as their size followed by the serialized data
+    let args_data = serialize(&mut (args.clone(), env.clone()));
+    if args_data.size() as u64 + 8 > USER_ARGS_SIZE {
+        return Err(RunProgramError::ArgumentsTooLarge);
+    }
+
+    let program = read_file(path).ok_or(RunProgramError::FileNotFound)?;
+    let (elf_header, segments) = parse_program(&program)?;
+
+    // the stack, arguments, context and a few pages for the page table itself
+    let mut needed_pages = USER_STACK_SIZE / PAGE_SIZE + USER_ARGS_SIZE / PAGE_SIZE + 1 + 4;
+    for header in &segments {
+        needed_pages += (header.vaddr + header.memory_size).div_ceil(PAGE_SIZE) - header.vaddr / PAGE_SIZE + 2;
+    }
+    if get_num_free_pages() < needed_pages {
+        return Err(RunProgramError::NoMemory);
+    }
+
+    // children start with the priority and affinity of their parent
+    let (nice, affinity) = parent_pid.map_or_else(|| (0, get_all_cores()), get_scheduling_params);
+
     PROCTABLE_ALLOC_LOCK.spinlock();
-    let free_proc = get_free_proc();
+    let Some(free_proc) = get_free_proc() else {
+        PROCTABLE_ALLOC_LOCK.unlock();
+        return Err(RunProgramError::NoMemory);
+    };
 
-    PROCTABLE_LOCKS[free_proc].spinlock();
+    get_lock(free_proc).spinlock();
     unsafe {
-        PROCTABLE[free_proc].0 = (Some(Process {
+        get_slot(free_proc).process = (Some(Process {
             state: ProcessState::Loading,
-            needs_paging_refresh: [true; NUM_CORES],
+            needs_paging_refresh: u64::MAX,
+            parent_pid,
+            fd_table,
+            path: path.clone(),
+            start_tick: get_ticks(),
+            cpu_ticks: 0,
+            killed: false,
+            thread_of: None,
+            context: USER_CONTEXT,
+            nice,
+            vruntime: MIN_VRUNTIME.load(Ordering::Relaxed),
+            affinity,
+            last_core: get_core_id() as usize,
+            stack_limit: DEFAULT_STACK_LIMIT,
+            started_by_kernel: parent_pid.is_none(),
         }));
     }
-    let page_table = unsafe { PROCTABLE[free_proc].1 };
-    PROCTABLE_LOCKS[free_proc].unlock();
+    let page_table = unsafe { get_slot(free_proc).page_table };
+    get_lock(free_proc).unlock();
     PROCTABLE_ALLOC_LOCK.unlock();
 
+    // the caller may be a process in a syscall, so its page table has to be restored at the end
+    let prev_page_table = get_current_page_table();
     switch_to_page_table(page_table);
 
-    // get program headers
-    let mut program_headers = Vec::new();
-    for i in 0..elf_header.ph_entry_count {
-        let program_header = unsafe { (program.as_ptr().add(elf_header.ph_offset as usize) as *const ElfProgramHeader).add(i as usize).read() };
-        program_headers.push(program_header);
+    load_segments(&program, &segments);
+    setup_stack_and_args(&args_data, elf_header.entry);
+
+    let t = NUM_PROCESSES.borrow();
+    *NUM_PROCESSES.get_mut(&t) += 1;
+    NUM_PROCESSES.release(t);
+
+    get_lock(free_proc).spinlock();
+    unsafe {
+        make_ready(free_proc, get_slot(free_proc).process.as_mut().unwrap());
     }
+    get_lock(free_proc).unlock();
 
-    // map program headers to memory
-    for header in &program_headers {
-        if header.p_type == 1 && header.memory_size != 0 {
-            #[cfg(feature = "assertions")]
-            assert!(header.vaddr >= KERNEL_VIRTUAL_TOP);
+    switch_to_page_table(prev_page_table);
 
-            let low_page = header.vaddr / PAGE_SIZE;
-            let high_page = (header.vaddr + header.memory_size).div_ceil(PAGE_SIZE);
-            for page in low_page..high_page {
-                map_page_auto((page * PAGE_SIZE) as VirtAddr, true, true, true, true);
-            }
+    Ok(free_proc)
+}
 
-            #[cfg(feature = "assertions")]
-            assert!(header.memory_size >= header.file_size);
-            let ptr_low = header.vaddr as *mut u8;
-            let ptr_mid = (header.vaddr + header.file_size) as *mut u8;
-            unsafe {
-                copy(program.as_ptr().add(header.offset as usize), ptr_low, header.file_size as usize);
-                write_bytes(ptr_mid, 0, (header.memory_size - header.file_size) as usize);
-            }
-        }
+// the pid of the main thread of the process
+pub fn get_thread_owner(pid: usize) -> usize {
+    get_lock(pid).spinlock();
+    let res = unsafe { get_slot(pid).process.as_ref().unwrap().thread_of.unwrap_or(pid) };
+    get_lock(pid).unlock();
+    res
+}
+
+// starts a thread of the calling process at entry with a0 and a1 set, it gets its own context page and stack
+pub fn create_thread(pid: usize, entry: u64, a0: u64, a1: u64) -> Option<usize> {
+    let owner = get_thread_owner(pid);
+
+    get_lock(owner).spinlock();
+    let path = unsafe { get_slot(owner).process.as_ref().unwrap().path.clone() };
+    get_lock(owner).unlock();
+    let (nice, affinity) = get_scheduling_params(pid);
+
+    PROCTABLE_ALLOC_LOCK.spinlock();
+    let Some(tid) = get_free_proc() else {
+        PROCTABLE_ALLOC_LOCK.unlock();
+        return None;
+    };
+
+    // every pid has its own place for the context page and stack of a thread
+    let context_addr = USER_THREADS + tid as u64 * USER_THREAD_SIZE;
+    if context_addr + USER_THREAD_SIZE > USER_THREADS_END {
+        PROCTABLE_ALLOC_LOCK.unlock();
+        return None;
     }
 
-    #[cfg(feature = "assertions")]
-    assert_eq!(USER_STACK_SIZE % PAGE_SIZE, 0);
-    let stack_pages = USER_STACK_SIZE / PAGE_SIZE;
-    let stack_top = USER_STACK + USER_STACK_SIZE;
-    for i in 0..stack_pages {
-        map_page_auto((USER_STACK + i * PAGE_SIZE) as VirtAddr, true, true, true, false);
+    get_lock(tid).spinlock();
+    unsafe {
+        get_slot(tid).process = Some(Process {
+            state: ProcessState::Loading,
+            needs_paging_refresh: u64::MAX,
+            parent_pid: Some(pid),
+            fd_table: FdTable::new(),
+            path,
+            start_tick: get_ticks(),
+            cpu_ticks: 0,
+            killed: false,
+            thread_of: Some(owner),
+            context: context_addr,
+            nice,
+            vruntime: MIN_VRUNTIME.load(Ordering::Relaxed),
+            affinity,
+            last_core: get_core_id() as usize,
+            stack_limit: 0,
+            started_by_kernel: false,
+        });
+    }
+    get_lock(tid).unlock();
+    PROCTABLE_ALLOC_LOCK.unlock();
+
+    // the caller is running a syscall, so the shared page table is the current one
+    get_shared_lock(owner).spinlock();
+    map_page_auto(context_addr as VirtAddr, false, true, false, false);
+    let stack = context_addr + PAGE_SIZE;
+    for i in 0..USER_STACK_SIZE / PAGE_SIZE {
+        reserve_page((stack + i * PAGE_SIZE) as VirtAddr, true, true, false);
     }
+    get_shared_lock(owner).unlock();
 
-    map_page_auto(USER_CONTEXT as VirtAddr, true, true, false, false);
+    let context = unsafe {
+        write_bytes(context_addr as *mut u8, 0, size_of::<Context>());
+        &mut *(context_addr as *mut Context)
+    };
+    context.pc = entry;
+    context.sp = stack + USER_STACK_SIZE;
+    context.a0 = a0;
+    context.a1 = a1;
+
+    let t = NUM_PROCESSES.borrow();
+    *NUM_PROCESSES.get_mut(&t) += 1;
+    NUM_PROCESSES.release(t);
+
+    // the process may have been killed while the thread was created
+    let killed = is_process_killed(pid);
+
+    get_lock(tid).spinlock();
     unsafe {
-        write_bytes(USER_CONTEXT as *mut u8, 0, size_of::<Context>());
+        let thread = get_slot(tid).process.as_mut().unwrap();
+        thread.killed = killed;
+        make_ready(tid, thread);
+    }
+    get_lock(tid).unlock();
+
+    Some(tid)
+}
+
+// what a forked child gets instead of its pid as the result of the syscall
+pub const FORK_CHILD: u64 = u64::MAX - 1;
+
+// the child shares the memory of the process copy-on-write, gets duplicates of its files under the same numbers and
+// continues from the context of the main thread. returns the pid of the child, None if pid is another thread, whose
+// stack is not part of the child
+pub fn fork_process(pid: usize) -> Option<usize> {
+    if get_thread_owner(pid) != pid {
+        return None;
     }
 
-    get_context().pc = elf_header.entry;
-    get_context().sp = stack_top;
+    get_lock(pid).spinlock();
+    let (path, stack_limit, started_by_kernel) = unsafe {
+        let process = get_slot(pid).process.as_ref().unwrap();
+        (process.path.clone(), process.stack_limit, process.started_by_kernel)
+    };
+    get_lock(pid).unlock();
+    let (nice, affinity) = get_scheduling_params(pid);
+
+    // the context page of the thread is only mapped in the page table of the process
+    let mut context_data = [0u8; size_of::<Context>()];
+    unsafe {
+        copy(get_context() as *const Context as *const u8, context_data.as_mut_ptr(), size_of::<Context>());
+    }
+
+    // the context page and a few pages for the page tables
+    if get_num_free_pages() < 8 {
+        return None;
+    }
+    let fd_table = with_fd_table(pid, &mut |fd_table| fd_table.duplicate());
+
+    PROCTABLE_ALLOC_LOCK.spinlock();
+    let Some(child) = get_free_proc() else {
+        PROCTABLE_ALLOC_LOCK.unlock();
+        return None;
+    };
+
+    get_lock(child).spinlock();
+    unsafe {
+        get_slot(child).process = Some(Process {
+            state: ProcessState::Loading,
+            needs_paging_refresh: u64::MAX,
+            parent_pid: Some(pid),
+            fd_table,
+            path,
+            start_tick: get_ticks(),
+            cpu_ticks: 0,
+            killed: false,
+            thread_of: None,
+            context: USER_CONTEXT,
+            nice,
+            vruntime: MIN_VRUNTIME.load(Ordering::Relaxed),
+            affinity,
+            last_core: get_core_id() as usize,
+            stack_limit,
+            started_by_kernel,
+        });
+    }
+    let page_table = unsafe { get_slot(child).page_table };
+    get_lock(child).unlock();
+    PROCTABLE_ALLOC_LOCK.unlock();
+
+    // the threads of the process have their context pages and stacks there, the child only has the calling thread
+    let cloned = with_page_table(pid, &mut || clone_user_pages(get_current_page_table(), page_table, USER_THREADS..USER_THREADS_END));
+    // the writable pages of the process are read-only now, also for its threads running on other cores
+    shoot_down_tlb(pid);
+
+    if !cloned {
+        clear_page_table(page_table);
+        get_lock(child).spinlock();
+        unsafe {
+            get_slot(child).process = None;
+        }
+        get_lock(child).unlock();
+        return None;
+    }
+
+    let prev_page_table = get_current_page_table();
+    switch_to_page_table(page_table);
+    map_page_auto(USER_CONTEXT as VirtAddr, false, true, false, false);
+    let context = unsafe {
+        copy(context_data.as_ptr(), USER_CONTEXT as *mut u8, size_of::<Context>());
+        &mut *(USER_CONTEXT as *mut Context)
+    };
+    context.a2 = FORK_CHILD;
+    switch_to_page_table(prev_page_table);
 
     let t = NUM_PROCESSES.borrow();
     *NUM_PROCESSES.get_mut(&t) += 1;
     NUM_PROCESSES.release(t);
 
-    PROCTABLE_LOCKS[free_proc].spinlock();
+    get_lock(child).spinlock();
     unsafe {
-        PROCTABLE[free_proc].0.as_mut().unwrap().state = ProcessState::Ready;
+        make_ready(child, get_slot(child).process.as_mut().unwrap());
     }
-    PROCTABLE_LOCKS[free_proc].unlock();
+    get_lock(child).unlock();
+
+    Some(child)
 }
 
-extern "C" {
-    fn jump_to_user() -> !;
+// how far below the stack pointer a fault may be for the stack to grow, a function pushes before it moves it
+pub const STACK_GROWTH_SLACK: u64 = 2 * PAGE_SIZE;
+
+// a fault just below the stack pointer sp of the main thread reserves the stack down to the faulting page in the page
+// table of the process, which may not be the current one. returns false if the fault is further below sp or the stack
+// would get larger than the limit of the process
+pub fn grow_stack(pid: usize, addr: u64, sp: u64) -> bool {
+    if addr < sp.saturating_sub(STACK_GROWTH_SLACK) || !(USER_STACK_END - get_stack_limit(pid)..USER_STACK_END).contains(&addr) {
+        return false;
+    }
+
+    let owner = get_thread_owner(pid);
+    get_shared_lock(owner).spinlock();
+    let prev_page_table = get_current_page_table();
+    let page_table = unsafe { get_slot(owner).page_table };
+    switch_to_page_table(page_table);
+    let mut page = addr / PAGE_SIZE * PAGE_SIZE;
+    while page < USER_STACK_END && !is_page_mapped(page_table, page) && !is_page_reserved(page_table, page) {
+        reserve_page(page as VirtAddr, true, true, false);
+        page += PAGE_SIZE;
+    }
+    switch_to_page_table(prev_page_table);
+    get_shared_lock(owner).unlock();
+    true
+}
+
+pub fn get_stack_limit(pid: usize) -> u64 {
+    let owner = get_thread_owner(pid);
+    get_lock(owner).spinlock();
+    let res = unsafe { get_slot(owner).process.as_ref().unwrap().stack_limit };
+    get_lock(owner).unlock();
+    res
 }
 
-pub fn scheduler_next_proc() {
-    get_cpu_data().curr_pid += 1;
-    get_cpu_data().curr_pid %= NUM_PROC;
+// the limit is rounded up to whole pages, returns false if it is not between USER_STACK_SIZE and USER_STACK_MAX_SIZE.
+// a stack that is larger already keeps its pages
+pub fn set_stack_limit(pid: usize, limit: u64) -> bool {
+    if !(USER_STACK_SIZE..=USER_STACK_MAX_SIZE).contains(&limit) {
+        return false;
+    }
+    let owner = get_thread_owner(pid);
+    get_lock(owner).spinlock();
+    unsafe {
+        get_slot(owner).process.as_mut().unwrap().stack_limit = limit.next_multiple_of(PAGE_SIZE);
+    }
+    get_lock(owner).unlock();
+    true
+}
+
+// the context page and stack are removed from the shared page table, which may not be the current one. returns their
+// memory, other threads of the process may still access it until their cores have flushed their tlb
+fn take_thread_memory(pid: usize, owner: usize) -> Vec<PhysAddr> {
+    let context_addr = unsafe { get_slot(pid).process.as_ref().unwrap().context };
+    let mut res = Vec::new();
+
+    get_shared_lock(owner).spinlock();
+    let prev_page_table = get_current_page_table();
+    switch_to_page_table(unsafe { get_slot(owner).page_table });
+    if let Some(phys_addr) = virt_to_phys(context_addr as VirtAddr) {
+        unmap_page(context_addr as VirtAddr);
+        res.push(phys_addr);
+    }
+    // the thread may have freed pages of its stack itself, the rest may be reserved or shared with a forked child
+    for i in 1..USER_THREAD_SIZE / PAGE_SIZE {
+        take_user_page(get_current_page_table(), context_addr + i * PAGE_SIZE, &mut res);
+    }
+    switch_to_page_table(prev_page_table);
+    get_shared_lock(owner).unlock();
+    res
+}
+
+extern "C" {
+    fn jump_to_user(context: u64) -> !;
 }
 
 static mut SCHEDULER_ENABLED: bool = true;
@@ -216,43 +762,142 @@ pub fn get_num_processes() -> usize {
     res
 }
 
-pub fn scheduler() -> ! {
-    let mut misses = 0;
-    loop {
-        unsafe {
-            if !SCHEDULER_ENABLED {
-                asm!("wfi");
-                continue;
-            }
+// a ready process waits in the run queue of exactly one core until a core takes it out to run it
+#[derive(Clone, Copy)]
+struct QueueEntry {
+    pid: usize,
+    vruntime: u64,
+    affinity: u64,
+}
+
+static mut RUN_QUEUES: Option<Vec<Mutable<Vec<QueueEntry>>>> = None;
+
+fn get_run_queue(core: usize) -> &'static Mutable<Vec<QueueEntry>> {
+    unsafe { &(*addr_of!(RUN_QUEUES)).as_ref().unwrap()[core] }
+}
+
+fn get_queue_size(core: usize) -> usize {
+    let t = get_run_queue(core).borrow();
+    let res = get_run_queue(core).get(&t).size();
+    get_run_queue(core).release(t);
+    res
+}
+
+// the process slot has to be locked, the process goes to the allowed core with the shortest queue,
+// preferring the one it ran on last
+fn make_ready(pid: usize, process: &mut Process) {
+    process.state = ProcessState::Ready;
+
+    let mut best: Option<(usize, usize)> = None;
+    for i in 0..get_num_cores() {
+        let core = (process.last_core + i) % get_num_cores();
+        if process.affinity & (1 << core) == 0 {
+            continue;
         }
+        let size = get_queue_size(core);
+        if best.is_none_or(|(_, best_size)| size < best_size) {
+            best = Some((core, size));
+        }
+    }
 
-        if misses == NUM_PROC {
-            misses = 0;
-            check_screen_refresh_for_print();
-            unsafe {
-                asm!("wfi");
-            }
+    let (core, _) = best.unwrap();
+    let t = get_run_queue(core).borrow();
+    get_run_queue(core).get_mut(&t).push(QueueEntry { pid, vruntime: process.vruntime, affinity: process.affinity });
+    get_run_queue(core).release(t);
+    wake_core(core);
+}
+
+// a process that was blocked for long does not get to catch up on all the time it missed
+fn wake_up(pid: usize, process: &mut Process) {
+    process.vruntime = max(process.vruntime, MIN_VRUNTIME.load(Ordering::Relaxed));
+    make_ready(pid, process);
+}
+
+// the entry with the lowest vruntime that may run on the core, the earlier one on ties so equal processes take turns
+fn find_entry(queue: &Vec<QueueEntry>, core: usize) -> Option<usize> {
+    let mut res: Option<usize> = None;
+    for i in 0..queue.size() {
+        if queue[i].affinity & (1 << core) != 0 && res.is_none_or(|best| queue[i].vruntime < queue[best].vruntime) {
+            res = Some(i);
+        }
+    }
+    res
+}
+
+fn take_entry(queue_core: usize, core: usize) -> Option<QueueEntry> {
+    let t = get_run_queue(queue_core).borrow();
+    let queue = get_run_queue(queue_core).get_mut(&t);
+    let res = find_entry(queue, core).map(|i| queue.remove(i));
+    get_run_queue(queue_core).release(t);
+    res
+}
+
+// takes work from the core with the longest queue that has a process this core may run
+fn steal_entry(core: usize) -> Option<QueueEntry> {
+    let mut victim: Option<(usize, usize)> = None;
+    for other in 0..get_num_cores() {
+        if other == core {
+            continue;
+        }
+        let t = get_run_queue(other).borrow();
+        let queue = get_run_queue(other).get(&t);
+        if victim.is_none_or(|(_, size)| queue.size() > size) && find_entry(queue, core).is_some() {
+            victim = Some((other, queue.size()));
         }
+        get_run_queue(other).release(t);
+    }
 
-        let pid = get_cpu_data().curr_pid;
+    // the entry may have been taken in the meantime, the scheduler just tries again later
+    take_entry(victim?.0, core)
+}
 
-        PROCTABLE_LOCKS[pid].spinlock();
+// returns the next process for this core with its slot locked, stealing from other cores if its own queue is empty
+fn pick_next_process() -> Option<usize> {
+    let core = get_core_id() as usize;
+    loop {
+        let entry = take_entry(core, core).or_else(|| steal_entry(core))?;
+        let pid = entry.pid;
 
-        unsafe {
-            if let Some(ProcessState::Sleeping(until)) = PROCTABLE[pid].0.as_ref().map(|p| &p.state) {
-                if *until <= get_ticks() {
-                    PROCTABLE[pid].0.as_mut().unwrap().state = ProcessState::Ready;
-                }
-            }
+        get_lock(pid).spinlock();
+        let process = unsafe { get_slot(pid).process.as_mut().unwrap() };
+        #[cfg(feature = "assertions")]
+        assert!(process.state == ProcessState::Ready);
 
-            if PROCTABLE[pid].0.is_none() || PROCTABLE[pid].0.as_ref().unwrap().state != ProcessState::Ready {
-                misses += 1;
-                scheduler_next_proc();
-                PROCTABLE_LOCKS[pid].unlock();
+        if process.killed {
+            let thread = release_process(pid, KILLED_EXIT_CODE);
+            get_lock(pid).unlock();
+            after_release(pid, thread);
+        } else if process.affinity & (1 << core) == 0 {
+            // the affinity changed while it was queued
+            make_ready(pid, process);
+            get_lock(pid).unlock();
+        } else {
+            MIN_VRUNTIME.fetch_max(process.vruntime, Ordering::Relaxed);
+            return Some(pid);
+        }
+    }
+}
+
+pub fn scheduler() -> ! {
+    loop {
+        park_if_halted();
+        acknowledge_tlb_flush();
+        unsafe {
+            if !SCHEDULER_ENABLED {
+                asm!("wfi");
                 continue;
             }
         }
 
+        wake_sleepers();
+        wake_deferred();
+
+        let Some(pid) = pick_next_process() else {
+            check_screen_refresh_for_print();
+            idle();
+            continue;
+        };
+
         interrupts_enable(false);
 
         // clear bit in sstatus
@@ -264,64 +909,503 @@ pub fn scheduler() -> ! {
         switch_to_user_trap();
 
         unsafe {
-            switch_to_page_table(PROCTABLE[pid].1);
+            let process = get_slot(pid).process.as_ref().unwrap();
+            switch_to_page_table(get_slot(process.thread_of.unwrap_or(pid)).page_table);
+            get_cpu_data().context = process.context;
 
-            if PROCTABLE[pid].0.as_ref().unwrap().needs_paging_refresh[get_core_id() as usize] {
+            if get_slot(pid).process.as_ref().unwrap().needs_paging_refresh & (1 << get_core_id()) != 0 {
                 refresh_paging();
-                PROCTABLE[pid].0.as_mut().unwrap().needs_paging_refresh[get_core_id() as usize] = false;
+                get_slot(pid).process.as_mut().unwrap().needs_paging_refresh &= !(1 << get_core_id());
             }
 
-            PROCTABLE[pid].0.as_mut().unwrap().state = ProcessState::Running;
+            let process = get_slot(pid).process.as_mut().unwrap();
+            process.state = ProcessState::Running;
+            process.last_core = get_core_id() as usize;
             get_cpu_data().last_pid = pid;
-            PROCTABLE_LOCKS[pid].unlock();
+            get_cpu_data().run_start = get_ticks();
+            get_lock(pid).unlock();
 
-            jump_to_user();
+            jump_to_user(get_cpu_data().context);
         }
     }
 }
 
+// the nice value and affinity
+fn get_scheduling_params(pid: usize) -> (i32, u64) {
+    get_lock(pid).spinlock();
+    let res = unsafe {
+        let process = get_slot(pid).process.as_ref().unwrap();
+        (process.nice, process.affinity)
+    };
+    get_lock(pid).unlock();
+    res
+}
+
+// returns false if there is no such process or the value is out of range
+pub fn set_nice(pid: usize, nice: i32) -> bool {
+    if pid >= get_num_slots() || !(MIN_NICE..=MAX_NICE).contains(&nice) {
+        return false;
+    }
+
+    get_lock(pid).spinlock();
+    let res = unsafe {
+        match get_slot(pid).process.as_mut() {
+            Some(process) if !matches!(process.state, ProcessState::Exiting(_) | ProcessState::Zombie(_)) => {
+                process.nice = nice;
+                true
+            }
+            _ => false,
+        }
+    };
+    get_lock(pid).unlock();
+    res
+}
+
+// returns false if there is no such process or the mask has no existing core,
+// a queued process moves on the next time it is picked
+pub fn set_affinity(pid: usize, affinity: u64) -> bool {
+    if pid >= get_num_slots() || affinity & get_all_cores() == 0 {
+        return false;
+    }
+
+    get_lock(pid).spinlock();
+    let res = unsafe {
+        match get_slot(pid).process.as_mut() {
+            Some(process) if !matches!(process.state, ProcessState::Exiting(_) | ProcessState::Zombie(_)) => {
+                process.affinity = affinity & get_all_cores();
+                true
+            }
+            _ => false,
+        }
+    };
+    get_lock(pid).unlock();
+    res
+}
+
+// adds the time since the process started running on this core
+pub fn account_cpu_time(pid: usize) {
+    get_lock(pid).spinlock();
+
+    unsafe {
+        let process = get_slot(pid).process.as_mut().unwrap();
+        let ticks = get_ticks() - get_cpu_data().run_start;
+        process.cpu_ticks += ticks;
+        process.vruntime += ticks * VRUNTIME_PER_TICK / get_nice_weight(process.nice);
+    }
+
+    get_lock(pid).unlock();
+}
+
+pub fn list_processes() -> Vec<ProcessInfo> {
+    let mut res = Vec::new();
+    for pid in 0..get_num_slots() {
+        get_lock(pid).spinlock();
+        if let Some(process) = unsafe { get_slot(pid).process.as_ref() } {
+            // after loading the page table is only changed under the shared lock or while the slot is locked for releasing it
+            let memory_pages = if process.thread_of.is_none() && process.state != ProcessState::Loading {
+                get_shared_lock(pid).spinlock();
+                let res = count_user_pages(unsafe { get_slot(pid).page_table });
+                get_shared_lock(pid).unlock();
+                Some(res)
+            } else {
+                None
+            };
+            res.push(ProcessInfo {
+                pid,
+                parent_pid: process.parent_pid,
+                state: process.state.name(),
+                nice: process.nice,
+                path: process.path.clone(),
+                start_tick: process.start_tick,
+                cpu_ticks: process.cpu_ticks,
+                core: process.last_core,
+                memory_pages,
+            });
+        }
+        get_lock(pid).unlock();
+    }
+    res
+}
+
 pub fn mark_process_ready(pid: usize) {
-    PROCTABLE_LOCKS[pid].spinlock();
+    get_lock(pid).spinlock();
 
     unsafe {
-        PROCTABLE[pid].0.as_mut().unwrap().state = ProcessState::Ready;
+        make_ready(pid, get_slot(pid).process.as_mut().unwrap());
     }
 
-    PROCTABLE_LOCKS[pid].unlock();
+    get_lock(pid).unlock();
 }
 
-pub fn put_process_to_sleep(pid: usize, until: u64) {
-    PROCTABLE_LOCKS[pid].spinlock();
+// the process has to be running, it is woken up with wake_process and the same channel
+pub fn block_process(pid: usize, channel: usize) {
+    get_lock(pid).spinlock();
 
     unsafe {
-        PROCTABLE[pid].0.as_mut().unwrap().state = ProcessState::Sleeping(until);
+        let process = get_slot(pid).process.as_mut().unwrap();
+        // it was killed while running, so the scheduler releases it instead
+        if process.killed {
+            make_ready(pid, process);
+        } else {
+            process.state = ProcessState::Blocked(channel);
+        }
     }
 
-    PROCTABLE_LOCKS[pid].unlock();
+    get_lock(pid).unlock();
 }
 
-pub fn terminate_process(pid: usize) {
-    PROCTABLE_LOCKS[pid].spinlock();
+// returns false if the process is not blocked on this channel (anymore)
+pub fn wake_process(pid: usize, channel: usize) -> bool {
+    if pid >= get_num_slots() {
+        return false;
+    }
+
+    get_lock(pid).spinlock();
+    let res = unsafe {
+        match get_slot(pid).process.as_mut() {
+            Some(process) if process.state == ProcessState::Blocked(channel) => {
+                wake_up(pid, process);
+                true
+            }
+            _ => false,
+        }
+    };
+    get_lock(pid).unlock();
+    res
+}
 
+fn free_proc(pid: usize) {
     unsafe {
-        clear_page_table(PROCTABLE[pid].1);
-        refresh_paging();
-        PROCTABLE[pid].0 = None;
+        get_slot(pid).process = None;
     }
 
     let t = NUM_PROCESSES.borrow();
     *NUM_PROCESSES.get_mut(&t) -= 1;
     NUM_PROCESSES.release(t);
+}
 
-    PROCTABLE_LOCKS[pid].unlock();
+// the process slot has to be locked, a blocked process is woken up so the scheduler can release it
+fn mark_killed(pid: usize, process: &mut Process) {
+    process.killed = true;
+    if let ProcessState::Blocked(_) = process.state {
+        wake_up(pid, process);
+    }
 }
 
-pub fn refresh_paging_for_proc(pid: usize) {
-    PROCTABLE_LOCKS[pid].spinlock();
+// marks all other threads of the process as killed, returns false if there are none left.
+// threads that have exited but were not joined are freed, nobody can join them anymore
+fn kill_threads(pid: usize) -> bool {
+    let mut res = false;
+    for thread_pid in 0..get_num_slots() {
+        if thread_pid == pid {
+            continue;
+        }
+
+        get_lock(thread_pid).spinlock();
+        unsafe {
+            if let Some(thread) = get_slot(thread_pid).process.as_mut() {
+                if thread.thread_of == Some(pid) {
+                    if let ProcessState::Zombie(_) = thread.state {
+                        free_proc(thread_pid);
+                    } else {
+                        mark_killed(thread_pid, thread);
+                        res = true;
+                    }
+                }
+            }
+        }
+        get_lock(thread_pid).unlock();
+    }
+    res
+}
 
+// the process slot must be locked, returns the main thread and the memory of the thread if this was another thread
+fn release_process(pid: usize, exit_code: i32) -> Option<(usize, Vec<PhysAddr>)> {
     unsafe {
-        PROCTABLE[pid].0.as_mut().unwrap().needs_paging_refresh = [true; NUM_CORES];
+        let thread_of = get_slot(pid).process.as_ref().unwrap().thread_of;
+        let res = if let Some(owner) = thread_of {
+            Some((owner, take_thread_memory(pid, owner)))
+        } else if kill_threads(pid) {
+            // the page table and files are still used by the other threads
+            get_slot(pid).process.as_mut().unwrap().state = ProcessState::Exiting(exit_code);
+            return None;
+        } else {
+            clear_page_table(get_slot(pid).page_table);
+            refresh_paging();
+            None
+        };
+
+        // the process stays as a zombie until its parent collects the exit code
+        let process = get_slot(pid).process.as_mut().unwrap();
+        process.fd_table = FdTable::new();
+        if process.parent_pid.is_some() {
+            process.state = ProcessState::Zombie(exit_code);
+        } else {
+            free_proc(pid);
+        }
+        res
+    }
+}
+
+// has to be called without the process slot locked, takes what release_process returned
+fn after_release(pid: usize, thread: Option<(usize, Vec<PhysAddr>)>) {
+    // the children that are threads have to be gone before the main thread can be released
+    orphan_children(pid);
+    if let Some((owner, memory)) = thread {
+        // the other threads may still have the stack in their tlb
+        shoot_down_tlb_of_owner(owner);
+        for page in memory {
+            release_page(page);
+        }
+        release_if_exiting(owner);
+    }
+    release_foreground_process(pid);
+    unsafe {
+        get_slot(pid).exit_queue.wake_all();
+    }
+}
+
+// the main thread is released once its last other thread is gone
+fn release_if_exiting(pid: usize) {
+    get_lock(pid).spinlock();
+    if let Some(ProcessState::Exiting(exit_code)) = unsafe { get_slot(pid).process.as_ref().map(|p| &p.state) } {
+        if !kill_threads(pid) {
+            let thread = release_process(pid, *exit_code);
+            get_lock(pid).unlock();
+            after_release(pid, thread);
+            return;
+        }
+    }
+    get_lock(pid).unlock();
+}
+
+// nobody can collect the children anymore
+fn orphan_children(pid: usize) {
+    for child_pid in 0..get_num_slots() {
+        if child_pid == pid {
+            continue;
+        }
+
+        get_lock(child_pid).spinlock();
+        unsafe {
+            if let Some(child) = get_slot(child_pid).process.as_mut() {
+                if child.parent_pid == Some(pid) {
+                    child.parent_pid = None;
+                    if let ProcessState::Zombie(_) = child.state {
+                        free_proc(child_pid);
+                    }
+                }
+            }
+        }
+        get_lock(child_pid).unlock();
+    }
+}
+
+// ends the process, or only the thread if it is not the main one
+pub fn terminate_process(pid: usize, exit_code: i32) {
+    get_lock(pid).spinlock();
+    let thread = release_process(pid, exit_code);
+    get_lock(pid).unlock();
+
+    after_release(pid, thread);
+}
+
+// the process may be running on another core, so it is only marked here and released by the scheduler
+pub fn kill_process(pid: usize) -> bool {
+    if pid >= get_num_slots() {
+        return false;
+    }
+
+    get_lock(pid).spinlock();
+    let res = unsafe {
+        match get_slot(pid).process.as_mut() {
+            Some(process) if !matches!(process.state, ProcessState::Exiting(_) | ProcessState::Zombie(_)) => {
+                mark_killed(pid, process);
+                true
+            }
+            _ => false,
+        }
+    };
+    get_lock(pid).unlock();
+    res
+}
+
+// true for processes the console or the kernel started, not for the ones other processes spawned
+pub fn is_started_by_kernel(pid: usize) -> bool {
+    let owner = get_thread_owner(pid);
+    get_lock(owner).spinlock();
+    let res = unsafe { get_slot(owner).process.as_ref().unwrap().started_by_kernel };
+    get_lock(owner).unlock();
+    res
+}
+
+// a process may only kill, renice or pin its children and the threads of its own process, including itself
+pub fn may_control(pid: usize, target: usize) -> bool {
+    if target >= get_num_slots() {
+        return false;
     }
 
-    PROCTABLE_LOCKS[pid].unlock();
+    get_lock(target).spinlock();
+    let target_info = unsafe { get_slot(target).process.as_ref().map(|process| (process.parent_pid, process.thread_of.unwrap_or(target))) };
+    get_lock(target).unlock();
+
+    let Some((parent_pid, owner)) = target_info else {
+        return false;
+    };
+    parent_pid == Some(pid) || owner == get_thread_owner(pid)
+}
+
+// false once the process has exited, even if its parent has not collected it yet
+pub fn process_exists(pid: usize) -> bool {
+    if pid >= get_num_slots() {
+        return false;
+    }
+
+    get_lock(pid).spinlock();
+    let res = unsafe { get_slot(pid).process.as_ref().is_some_and(|p| !matches!(p.state, ProcessState::Exiting(_) | ProcessState::Zombie(_))) };
+    get_lock(pid).unlock();
+    res
 }
+
+pub fn is_process_killed(pid: usize) -> bool {
+    get_lock(pid).spinlock();
+    let res = unsafe { get_slot(pid).process.as_ref().unwrap().killed };
+    get_lock(pid).unlock();
+    res
+}
+
+fn has_child_exited(child_pid: usize) -> bool {
+    get_lock(child_pid).spinlock();
+    let res = unsafe {
+        match get_slot(child_pid).process.as_ref() {
+            Some(child) => matches!(child.state, ProcessState::Zombie(_)),
+            None => true,
+        }
+    };
+    get_lock(child_pid).unlock();
+    res
+}
+
+pub enum ChildStatus {
+    Exited(i32),
+    Running,
+    NotChild,
+}
+
+// frees the child if it has exited and returns its exit code
+pub fn collect_child(pid: usize, child_pid: usize) -> ChildStatus {
+    if child_pid >= get_num_slots() {
+        return ChildStatus::NotChild;
+    }
+
+    get_lock(child_pid).spinlock();
+    let res = unsafe {
+        match get_slot(child_pid).process.as_ref() {
+            Some(child) if child.parent_pid == Some(pid) => {
+                if let ProcessState::Zombie(exit_code) = child.state {
+                    free_proc(child_pid);
+                    ChildStatus::Exited(exit_code)
+                } else {
+                    ChildStatus::Running
+                }
+            }
+            _ => ChildStatus::NotChild,
+        }
+    };
+    get_lock(child_pid).unlock();
+    res
+}
+
+// blocks the process until the child has exited
+pub fn wait_for_child(pid: usize, child_pid: usize) {
+    unsafe {
+        get_slot(child_pid).exit_queue.wait_until(pid, &|| has_child_exited(child_pid));
+    }
+}
+
+// the table is shared by all threads of the process
+pub fn with_fd_table<T>(pid: usize, f: &mut dyn FnMut(&mut FdTable) -> T) -> T {
+    let owner = get_thread_owner(pid);
+    get_shared_lock(owner).spinlock();
+    // the main thread is not released while other threads can still run a syscall
+    let res = f(unsafe { &mut get_slot(owner).process.as_mut().unwrap().fd_table });
+    get_shared_lock(owner).unlock();
+    res
+}
+
+// for changing the page table of the calling process while its other threads may run
+pub fn with_page_table<T>(pid: usize, f: &mut dyn FnMut() -> T) -> T {
+    let owner = get_thread_owner(pid);
+    get_shared_lock(owner).spinlock();
+    let res = f();
+    get_shared_lock(owner).unlock();
+    res
+}
+
+// a bit for every core that has to flush its tlb before it runs a user program again
+static PENDING_TLB_FLUSH: AtomicU64 = AtomicU64::new(0);
+
+// every trap from a user program ends up in the scheduler loop, which calls this before it runs the next one
+fn acknowledge_tlb_flush() {
+    let bit = 1 << get_core_id();
+    if (PENDING_TLB_FLUSH.load(Ordering::Acquire) & bit) != 0 {
+        refresh_paging();
+        PENDING_TLB_FLUSH.fetch_and(!bit, Ordering::AcqRel);
+    }
+}
+
+// the pages copy-on-write faults on this core replaced are released once the other threads of the process can not read
+// them through their tlb anymore, the slot of the main thread may already be released
+pub fn release_replaced_pages(owner: usize) {
+    let pages = take_replaced_pages();
+    if pages.size() == 0 {
+        return;
+    }
+    shoot_down_tlb_of_owner(owner);
+    for page in pages {
+        release_page(page);
+    }
+}
+
+// every thread of the process has to flush its tlb before running again, and the other cores running one of them have
+// done so when this returns. they get an ipi under the firmware and trap on their next timer interrupt otherwise
+pub fn shoot_down_tlb(pid: usize) {
+    shoot_down_tlb_of_owner(get_thread_owner(pid));
+}
+
+// the slot of the main thread may already be released
+fn shoot_down_tlb_of_owner(owner: usize) {
+    let mut cores = 0u64;
+    for thread_pid in 0..get_num_slots() {
+        get_lock(thread_pid).spinlock();
+
+        unsafe {
+            if let Some(thread) = get_slot(thread_pid).process.as_mut() {
+                if thread_pid == owner || thread.thread_of == Some(owner) {
+                    thread.needs_paging_refresh = u64::MAX;
+                    if thread.state == ProcessState::Running {
+                        cores |= 1 << thread.last_core;
+                    }
+                }
+            }
+        }
+
+        get_lock(thread_pid).unlock();
+    }
+    refresh_paging();
+
+    cores &= !(1 << get_core_id());
+    if cores == 0 {
+        return;
+    }
+    PENDING_TLB_FLUSH.fetch_or(cores, Ordering::AcqRel);
+    #[cfg(feature = "sbi")]
+    sbi_send_ipi(cores, 0);
+    // another core may be waiting for this one in the same way, and parked cores never flush
+    while (PENDING_TLB_FLUSH.load(Ordering::Acquire) & cores) != 0 {
+        park_if_halted();
+        acknowledge_tlb_flush();
+        spin_loop();
+    }
+}
+
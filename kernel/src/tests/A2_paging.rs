@@ -1,9 +1,12 @@
 use core::ptr::write_bytes;
 use kernel_test::{kernel_test, kernel_test_mod};
 
-use crate::memory::map_page;
+use crate::memory::{map_page, map_page_auto};
 use crate::memory::{alloc_page, free_page, unmap_page, PhysAddr, VirtAddr, PAGE_SIZE, TESTING_OFFSET};
-use kernel_std::Rng;
+use crate::memory::{clear_page_table, copy_from_user, copy_str_from_user, copy_to_user, create_page_table, get_current_page_table, switch_to_page_table, KERNEL_OFFSET, USER_STACK};
+use crate::memory::{fault_in_page, get_num_free_pages, is_page_mapped, is_page_reserved, reserve_page, unmap_user_page, PageAccess};
+use crate::memory::{clone_user_pages, get_page_refs, release_page, take_replaced_pages, user_virt_to_phys, USER_THREADS, USER_THREADS_END};
+use kernel_std::{Rng, String, Vec};
 
 kernel_test_mod!(crate::tests::A2_paging);
 
@@ -97,3 +100,153 @@ fn test_page_write_stays() {
         }
     }
 }
+
+#[kernel_test]
+fn test_user_copy() {
+    let prev_page_table = get_current_page_table();
+    let page_table = create_page_table();
+    switch_to_page_table(page_table);
+
+    let user_addr = USER_STACK + 10 * PAGE_SIZE;
+    map_page_auto(user_addr as VirtAddr, false, true, true, false);
+    map_page_auto((user_addr + PAGE_SIZE) as VirtAddr, false, true, true, false);
+    map_page_auto((user_addr + 2 * PAGE_SIZE) as VirtAddr, false, false, true, false);
+    map_page_auto((user_addr + 3 * PAGE_SIZE) as VirtAddr, false, true, false, false);
+
+    let mut data = [0u8; 100];
+    for i in 0..100 {
+        data[i] = i as u8;
+    }
+
+    // the copy crosses a page boundary
+    let addr = user_addr + PAGE_SIZE - 50;
+    let mut res = [0u8; 100];
+    assert!(copy_to_user(page_table, addr, &data));
+    assert!(copy_from_user(page_table, addr, &mut res));
+    assert_eq!(data, res);
+
+    // read only page
+    let addr = user_addr + 2 * PAGE_SIZE - 50;
+    assert!(!copy_to_user(page_table, addr, &data));
+    assert!(copy_from_user(page_table, addr, &mut res));
+
+    // kernel only page, unmapped page and kernel memory
+    assert!(!copy_from_user(page_table, user_addr + 3 * PAGE_SIZE - 50, &mut res));
+    assert!(!copy_from_user(page_table, user_addr + 4 * PAGE_SIZE, &mut res));
+    assert!(!copy_from_user(page_table, KERNEL_OFFSET, &mut res));
+    assert!(!copy_from_user(page_table, u64::MAX - 10, &mut res));
+
+    assert!(copy_to_user(page_table, user_addr, b"hello"));
+    assert!(copy_str_from_user(page_table, user_addr, 5) == Some(String::from("hello")));
+    assert!(copy_to_user(page_table, user_addr, &[0xff, 0xfe]));
+    assert!(copy_str_from_user(page_table, user_addr, 2).is_none());
+
+    switch_to_page_table(prev_page_table);
+    clear_page_table(page_table);
+    free_page(page_table as PhysAddr);
+}
+
+#[kernel_test]
+fn test_demand_paging() {
+    let prev_page_table = get_current_page_table();
+    let page_table = create_page_table();
+    switch_to_page_table(page_table);
+
+    let user_addr = USER_STACK + 10 * PAGE_SIZE;
+    reserve_page(user_addr as VirtAddr, true, true, false);
+    reserve_page((user_addr + PAGE_SIZE) as VirtAddr, false, true, false);
+    reserve_page((user_addr + 2 * PAGE_SIZE) as VirtAddr, true, false, false);
+    let free_pages = get_num_free_pages();
+
+    assert!(is_page_reserved(page_table, user_addr));
+    assert!(!is_page_mapped(page_table, user_addr));
+    // kernel only reservations are not for the user
+    assert!(!is_page_reserved(page_table, user_addr + 2 * PAGE_SIZE));
+    assert!(!fault_in_page(page_table, user_addr + 2 * PAGE_SIZE, PageAccess::Read));
+
+    // the copy maps the page and it starts zeroed
+    let mut res = [1u8; 10];
+    assert!(copy_from_user(page_table, user_addr + 100, &mut res));
+    assert_eq!(res, [0u8; 10]);
+    assert!(!is_page_reserved(page_table, user_addr));
+    assert!(is_page_mapped(page_table, user_addr));
+    assert_eq!(get_num_free_pages(), free_pages - 1);
+    assert!(fault_in_page(page_table, user_addr, PageAccess::Write));
+    assert_eq!(get_num_free_pages(), free_pages - 1);
+
+    // not writable, not executable and not reserved at all
+    assert!(!fault_in_page(page_table, user_addr + PAGE_SIZE, PageAccess::Write));
+    assert!(!fault_in_page(page_table, user_addr + PAGE_SIZE, PageAccess::Execute));
+    assert!(!fault_in_page(page_table, user_addr + 3 * PAGE_SIZE, PageAccess::Read));
+    assert!(!fault_in_page(page_table, KERNEL_OFFSET, PageAccess::Read));
+    assert!(is_page_reserved(page_table, user_addr + PAGE_SIZE));
+
+    // both a mapped page and a reservation can be removed once
+    assert!(unmap_user_page(page_table, user_addr));
+    assert!(unmap_user_page(page_table, user_addr + PAGE_SIZE));
+    assert!(!unmap_user_page(page_table, user_addr));
+    assert!(!unmap_user_page(page_table, user_addr + 2 * PAGE_SIZE));
+    assert_eq!(get_num_free_pages(), free_pages);
+
+    switch_to_page_table(prev_page_table);
+    clear_page_table(page_table);
+    free_page(page_table as PhysAddr);
+}
+
+#[kernel_test]
+fn test_copy_on_write() {
+    let prev_page_table = get_current_page_table();
+    let page_table = create_page_table();
+    let clone = create_page_table();
+    switch_to_page_table(page_table);
+
+    let user_addr = USER_STACK + 10 * PAGE_SIZE;
+    map_page_auto(user_addr as VirtAddr, false, true, true, false);
+    map_page_auto((user_addr + PAGE_SIZE) as VirtAddr, false, false, true, false);
+    reserve_page((user_addr + 2 * PAGE_SIZE) as VirtAddr, true, true, false);
+    assert!(copy_to_user(page_table, user_addr, b"parent"));
+
+    // the pages in the skipped range stay with the original
+    let skipped = USER_THREADS + PAGE_SIZE;
+    map_page_auto(skipped as VirtAddr, false, true, true, false);
+    assert!(clone_user_pages(page_table, clone, USER_THREADS..USER_THREADS_END));
+    assert!(!is_page_mapped(clone, skipped));
+    assert_eq!(get_page_refs(user_virt_to_phys(page_table, skipped, true).unwrap()), 0);
+    let page = user_virt_to_phys(page_table, user_addr, false).unwrap();
+    assert_eq!(user_virt_to_phys(clone, user_addr, false), Some(page));
+    assert_eq!(get_page_refs(page), 1);
+    // both are read-only until one of them writes, reservations stay reservations
+    assert!(user_virt_to_phys(page_table, user_addr, true).is_none());
+    assert!(user_virt_to_phys(clone, user_addr, true).is_none());
+    assert!(is_page_reserved(clone, user_addr + 2 * PAGE_SIZE));
+
+    // the first write gets a copy, the other one then has the page for itself
+    let free_pages = get_num_free_pages();
+    assert!(copy_to_user(clone, user_addr, b"child"));
+    assert_eq!(get_num_free_pages(), free_pages - 1);
+    // other cores may still read the shared page until they flush their tlb, so it is released afterwards
+    assert_eq!(get_page_refs(page), 1);
+    assert!(take_replaced_pages() == Vec::new_from_slice(&[page]));
+    release_page(page);
+    assert_eq!(get_page_refs(page), 0);
+    assert!(copy_to_user(page_table, user_addr + 6, b"!"));
+    assert_eq!(get_num_free_pages(), free_pages - 1);
+    assert_eq!(user_virt_to_phys(page_table, user_addr, true), Some(page));
+
+    assert!(copy_str_from_user(page_table, user_addr, 7) == Some(String::from("parent!")));
+    assert!(copy_str_from_user(clone, user_addr, 5) == Some(String::from("child")));
+
+    // a read-only page is shared but never writable
+    assert!(!copy_to_user(clone, user_addr + PAGE_SIZE, b"x"));
+    let read_only = user_virt_to_phys(page_table, user_addr + PAGE_SIZE, false).unwrap();
+    assert_eq!(get_page_refs(read_only), 1);
+
+    // the shared page is freed by the page table that releases it last
+    clear_page_table(clone);
+    assert_eq!(get_page_refs(read_only), 0);
+
+    switch_to_page_table(prev_page_table);
+    clear_page_table(page_table);
+    free_page(page_table as PhysAddr);
+    free_page(clone as PhysAddr);
+}
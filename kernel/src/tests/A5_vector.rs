@@ -269,6 +269,35 @@ fn test_vector_reverse() {
     }
 }
 
+#[kernel_test]
+fn test_vector_remove() {
+    let mut arr = [0; 1024];
+    let mut arr_size = 0;
+    let mut vec = Vec::new();
+
+    let mut rng = Rng::new(8943275023);
+
+    for _ in 0..1024 {
+        if rng.get(0, 2) == 0 && vec.size() != 0 {
+            let i = rng.get(0, vec.size() as u64) as usize;
+            assert_eq!(vec.remove(i), arr[i]);
+            for j in i..arr_size - 1 {
+                arr[j] = arr[j + 1];
+            }
+            arr_size -= 1;
+        } else {
+            arr[arr_size] = rng.get(0, 1 << 32) as u32;
+            vec.push(arr[arr_size]);
+            arr_size += 1
+        }
+
+        assert_eq!(arr_size, vec.size());
+        for j in 0..vec.size() {
+            assert_eq!(arr[j], vec[j]);
+        }
+    }
+}
+
 #[kernel_perf]
 struct PerfVecPush10 {
     rng: Rng,
@@ -1,71 +1,434 @@
 use kernel_std::{println, debugln, debug, String, Vec};
 use kernel_test::{kernel_test, kernel_test_mod};
-use crate::disk::filesystem::write_to_file;
-use crate::scheduler::{get_context, get_num_processes, run_program};
+use crate::disk::filesystem::{is_file, read_file, write_to_file};
+use crate::boot::get_num_cores;
+use crate::fd_table::{FdTable, IoStatus};
+use crate::input::{EventType, InputEvent};
+use crate::keyboard::{push_key_event, set_foreground_process, KEY_BACKSPACE, KEY_ENTER};
+use crate::scheduler::{get_context, get_nice_weight, get_num_processes, is_started_by_kernel, kill_process, list_processes, may_control, run_program, get_all_cores, set_affinity, set_nice, ProcessInfo, RunProgramError, MAX_NICE, MIN_NICE};
+use crate::scheduler::{get_stack_limit, grow_stack, set_stack_limit, STACK_GROWTH_SLACK};
+use crate::timer::Instant;
 use core::arch::asm;
-use crate::memory::{refresh_paging, virt_to_phys, USER_CONTEXT, VirtAddr};
-use crate::riscv::get_satp;
+use core::time::Duration;
+use crate::memory::{refresh_paging, virt_to_phys, PAGE_SIZE, USER_ARGS_SIZE, USER_CONTEXT, VirtAddr};
+use crate::memory::{clear_page_table, create_page_table, free_page, PhysAddr, USER_STACK};
+use crate::memory::{DEFAULT_STACK_LIMIT, USER_STACK_END, USER_STACK_GUARD, USER_STACK_MAX_SIZE, USER_STACK_SIZE};
+use crate::riscv::{get_core_id, get_satp};
 
 kernel_test_mod!(crate::tests::B0_scheduler);
 
+// how long the processes of a test may run before the test fails instead of hanging
+const TEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+macro_rules! test_program {
+    ($name:literal) => {
+        include_bytes!(concat!("../../../programs/", $name, "/target/riscv64gc-unknown-none-elf/release/test_program"))
+    };
+}
+
+fn store_test_program(name: &str, program: &[u8]) {
+    write_to_file(&String::from(name), &Vec::new_from_slice(program));
+}
+
+// false if some process is still running after the timeout
+fn wait_for_processes() -> bool {
+    let start = Instant::now();
+    while get_num_processes() > 0 {
+        if start.elapsed() > TEST_TIMEOUT {
+            return false;
+        }
+        unsafe {
+            asm!("wfi");
+        }
+    }
+    true
+}
+
+// the core running the tests does not run processes
+fn get_scheduling_core() -> usize {
+    (get_core_id() as usize + 1) % get_num_cores()
+}
+
+// lets the processes run for a while
+fn wait_for(duration: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        unsafe {
+            asm!("wfi");
+        }
+    }
+}
+
+fn get_process(pid: usize) -> ProcessInfo {
+    let mut res = None;
+    for process in list_processes() {
+        if process.pid == pid {
+            res = Some(process);
+        }
+    }
+    res.unwrap()
+}
+
+// runs the program without arguments until it exits and returns the file it left at the result path
+fn run_test_program(name: &str, program: &[u8], result: &str) -> Option<Vec<u8>> {
+    store_test_program(name, program);
+    assert_eq!(get_num_processes(), 0);
+
+    run_program(&String::from(name), &Vec::new(), &Vec::new(), None, FdTable::new()).unwrap();
+    assert!(wait_for_processes());
+
+    read_file(&String::from(result))
+}
+
+// test_program8 has to be stored, the process spins until it is killed
+fn start_spinner() -> usize {
+    run_program(&String::from("test_program8"), &Vec::new_from_slice(&[String::from("spin")]), &Vec::new(), None, FdTable::new()).unwrap()
+}
+
 #[kernel_test]
 fn test_one_process() {
-    let test_program = include_bytes!("../../../programs/test_program1/target/riscv64gc-unknown-none-elf/release/test_program");
-    let test_program_vec = Vec::new_from_slice(test_program);
-    write_to_file(&String::from("test_program1"), &test_program_vec);
+    store_test_program("test_program1", test_program!("test_program1"));
 
 
     for i in 0..10 {
         assert_eq!(get_num_processes(), 0);
 
-        run_program(&String::from("test_program1"));
+        run_program(&String::from("test_program1"), &Vec::new(), &Vec::new(), None, FdTable::new()).unwrap();
 
         assert_eq!(get_num_processes(), 1);
 
-        while get_num_processes() > 0 {
-            unsafe {
-                asm!("wfi");
-            }
-        }
+        assert!(wait_for_processes());
     }
 }
 
 #[kernel_test]
 fn test_process_spam() {
-    let test_program = include_bytes!("../../../programs/test_program2/target/riscv64gc-unknown-none-elf/release/test_program");
-    let test_program_vec = Vec::new_from_slice(test_program);
-    write_to_file(&String::from("test_program2"), &test_program_vec);
+    store_test_program("test_program2", test_program!("test_program2"));
 
 
     for i in 0..10000 {
         assert_eq!(get_num_processes(), 0);
 
-        run_program(&String::from("test_program2"));
+        run_program(&String::from("test_program2"), &Vec::new(), &Vec::new(), None, FdTable::new()).unwrap();
 
         assert_eq!(get_num_processes(), 1);
 
-        while get_num_processes() > 0 {
+        assert!(wait_for_processes());
+    }
+}
+
+#[kernel_test]
+fn test_thousand_processes() {
+    store_test_program("test_program1", test_program!("test_program1"));
+
+
+    for i in 0..1000 {
+        // the table grows, but memory can still run out while the earlier ones are running
+        while run_program(&String::from("test_program1"), &Vec::new(), &Vec::new(), None, FdTable::new()) == Err(RunProgramError::NoMemory) {
             unsafe {
                 asm!("wfi");
             }
         }
     }
+
+    assert!(wait_for_processes());
 }
 
 #[kernel_test]
-fn test_thousand_processes() {
-    let test_program = include_bytes!("../../../programs/test_program1/target/riscv64gc-unknown-none-elf/release/test_program");
-    let test_program_vec = Vec::new_from_slice(test_program);
-    write_to_file(&String::from("test_program1"), &test_program_vec);
+fn test_spawn() {
+    store_test_program("test_program2", test_program!("test_program2"));
+    store_test_program("test_program3", test_program!("test_program3"));
 
+    assert_eq!(get_num_processes(), 0);
 
-    for i in 0..1000 {
-        run_program(&String::from("test_program1"));
+    assert!(run_program(&String::from("test_program3"), &Vec::new(), &Vec::new(), None, FdTable::new()).is_ok());
+
+    assert!(wait_for_processes());
+}
+
+#[kernel_test]
+fn test_run_program_errors() {
+    let run = |path: &str, args: &Vec<String>| run_program(&String::from(path), args, &Vec::new(), None, FdTable::new());
+
+    assert_eq!(run("does_not_exist", &Vec::new()), Err(RunProgramError::FileNotFound));
+
+    write_to_file(&String::from("not_a_program"), &Vec::new_from_slice(&[0u8; 100]));
+    assert_eq!(run("not_a_program", &Vec::new()), Err(RunProgramError::InvalidElf));
+
+    store_test_program("test_program1", test_program!("test_program1"));
+    let mut long_arg = String::new();
+    for _ in 0..5 * 4096 {
+        long_arg.push('a');
     }
+    assert_eq!(run("test_program1", &Vec::new_from_slice(&[long_arg])), Err(RunProgramError::ArgumentsTooLarge));
 
-    while get_num_processes() > 0 {
+    assert_eq!(get_num_processes(), 0);
+}
+
+#[kernel_test]
+fn test_file_syscalls() {
+    let data = run_test_program("test_program4", test_program!("test_program4"), "fs_test/file").unwrap();
+    assert!(data == Vec::new_from_slice(b"hello there"));
+}
+
+#[kernel_test]
+fn test_file_bounds() {
+    let page_table = create_page_table();
+    let mut fd_table = FdTable::new();
+    let fd = fd_table.open(&String::from("fs_test/bounds"), true).unwrap();
+
+    // nothing is read past the end and nothing is allocated for bogus sizes or offsets
+    assert_eq!(fd_table.seek(fd, 100), Some(100));
+    assert!(matches!(fd_table.read(fd, page_table, USER_STACK, 10), IoStatus::Done(0)));
+    assert!(matches!(fd_table.write(fd, page_table, USER_STACK, usize::MAX), IoStatus::Failed));
+    assert!(matches!(fd_table.write(fd, page_table, USER_STACK, 1 << 30), IoStatus::Failed));
+    assert_eq!(fd_table.seek(fd, usize::MAX), Some(usize::MAX));
+    assert!(matches!(fd_table.write(fd, page_table, USER_STACK, 1), IoStatus::Failed));
+
+    // the buffer is not mapped
+    assert_eq!(fd_table.seek(fd, 0), Some(0));
+    assert!(matches!(fd_table.write(fd, page_table, USER_STACK, 5), IoStatus::Failed));
+    assert_eq!(read_file(&String::from("fs_test/bounds")).unwrap().size(), 0);
+
+    clear_page_table(page_table);
+    free_page(page_table as PhysAddr);
+}
+
+#[kernel_test]
+fn test_pipe() {
+    store_test_program("test_program6", test_program!("test_program6"));
+    let data = run_test_program("test_program5", test_program!("test_program5"), "pipe_test/result").unwrap();
+    assert!(data == Vec::new_from_slice(&10000u64.to_le_bytes()));
+}
+
+#[kernel_test]
+fn test_args() {
+    assert!(run_test_program("test_program7", test_program!("test_program7"), "args_test/result").is_some());
+}
+
+#[kernel_test]
+fn test_kill() {
+    store_test_program("test_program8", test_program!("test_program8"));
+
+    assert_eq!(get_num_processes(), 0);
+
+    // killed by the kernel, unrelated processes can not kill each other
+    let pid = start_spinner();
+    let other_pid = start_spinner();
+    assert!(!may_control(pid, other_pid));
+    assert!(!may_control(other_pid, pid));
+    assert!(may_control(pid, pid));
+    assert!(is_started_by_kernel(pid));
+    assert!(kill_process(pid));
+    assert!(kill_process(other_pid));
+
+    assert!(wait_for_processes());
+
+    // killed by its parent
+    run_program(&String::from("test_program8"), &Vec::new(), &Vec::new(), None, FdTable::new()).unwrap();
+
+    assert!(wait_for_processes());
+
+    assert!(is_file(&String::from("kill_test/result")));
+}
+
+#[kernel_test]
+fn test_threads() {
+    let n = 400000u64;
+    let data = run_test_program("test_program9", test_program!("test_program9"), "thread_test/result").unwrap();
+    assert!(data == Vec::new_from_slice(&(n * (n - 1) / 2).to_le_bytes()));
+}
+
+#[kernel_test]
+fn test_exit_with_unjoined_threads() {
+    assert!(run_test_program("test_program14", test_program!("test_program14"), "exit_test/result").is_some());
+}
+
+#[kernel_test]
+fn test_fork_with_threads() {
+    assert!(run_test_program("test_program13", test_program!("test_program13"), "fork_test/result").is_some());
+}
+
+#[kernel_test]
+fn test_futex() {
+    let data = run_test_program("test_program10", test_program!("test_program10"), "futex_test/result").unwrap();
+    assert!(data == Vec::new_from_slice(&40000u64.to_le_bytes()));
+}
+
+#[kernel_test]
+fn test_nice() {
+    for nice in MIN_NICE..MAX_NICE {
+        assert!(get_nice_weight(nice) > get_nice_weight(nice + 1));
+    }
+    assert_eq!(get_nice_weight(0), 1024);
+
+    assert!(run_test_program("test_program11", test_program!("test_program11"), "nice_test/result").is_some());
+
+    // of two busy processes on the same core the one with the lower nice value gets more of it
+    store_test_program("test_program8", test_program!("test_program8"));
+    let core = get_scheduling_core();
+    let mut pids = Vec::new();
+    for nice in [-5, 5] {
+        let pid = start_spinner();
+        assert!(set_affinity(pid, 1 << core));
+        assert!(set_nice(pid, nice));
+        pids.push(pid);
+    }
+
+    // measured from when both are running on the core
+    wait_for(Duration::from_millis(200));
+    let start_ticks = [get_process(pids[0]).cpu_ticks, get_process(pids[1]).cpu_ticks];
+    wait_for(Duration::from_secs(2));
+    let ticks = [get_process(pids[0]).cpu_ticks - start_ticks[0], get_process(pids[1]).cpu_ticks - start_ticks[1]];
+
+    for pid in &pids {
+        assert!(kill_process(*pid));
+    }
+    assert!(wait_for_processes());
+
+    assert!(ticks[0] > ticks[1]);
+}
+
+#[kernel_test]
+fn test_affinity() {
+    store_test_program("test_program8", test_program!("test_program8"));
+
+    assert_eq!(get_num_processes(), 0);
+
+    // all pinned to one core, the other cores must not steal them
+    let core = get_scheduling_core();
+    let mut pids = Vec::new();
+    for _ in 0..8 {
+        let pid = start_spinner();
+        assert!(set_affinity(pid, 1 << core));
+        pids.push(pid);
+    }
+
+    // every one of them got a timeslice after this, then the core is checked on every tick
+    wait_for(Duration::from_millis(200));
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(1) {
+        for pid in &pids {
+            assert_eq!(get_process(*pid).core, core);
+        }
+        unsafe {
+            asm!("wfi");
+        }
+    }
+
+    assert!(!set_affinity(pids[0], 0));
+    assert!(!set_affinity(pids[0], !get_all_cores()));
+    assert!(!set_affinity(100000, get_all_cores()));
+    assert!(set_affinity(pids[0], get_all_cores()));
+
+    for pid in &pids {
+        assert!(kill_process(*pid));
+    }
+
+    assert!(wait_for_processes());
+}
+
+#[kernel_test]
+fn test_work_stealing() {
+    store_test_program("test_program8", test_program!("test_program8"));
+
+    assert_eq!(get_num_processes(), 0);
+
+    // the core running the tests does not schedule, so the processes queued on it only run when the other cores steal them
+    let mut pids = Vec::new();
+    for _ in 0..2 * get_num_cores() {
+        let pid = start_spinner();
+        pids.push(pid);
+    }
+
+    // a process is on this core until it first runs, the time that takes depends on the load of the other cores
+    let test_core = get_core_id() as usize;
+    let start = Instant::now();
+    while (&pids).into_iter().any(|pid| get_process(*pid).core == test_core) && start.elapsed() < TEST_TIMEOUT {
         unsafe {
             asm!("wfi");
         }
     }
-}
\ No newline at end of file
+    for pid in &pids {
+        assert_ne!(get_process(*pid).core, test_core);
+    }
+
+    for pid in &pids {
+        assert!(kill_process(*pid));
+    }
+
+    assert!(wait_for_processes());
+}
+
+#[kernel_test]
+fn test_process_memory() {
+    store_test_program("test_program8", test_program!("test_program8"));
+
+    assert_eq!(get_num_processes(), 0);
+
+    let pid = start_spinner();
+
+    let mut found = false;
+    for process in list_processes() {
+        if process.pid == pid {
+            // at least the arguments and the context page are mapped, the stack only gets pages when it is used
+            assert!(process.memory_pages.unwrap() > USER_ARGS_SIZE / PAGE_SIZE);
+            found = true;
+        }
+    }
+    assert!(found);
+
+    assert!(kill_process(pid));
+    assert!(wait_for_processes());
+}
+
+#[kernel_test]
+fn test_keyboard() {
+    store_test_program("test_program12", test_program!("test_program12"));
+
+    assert_eq!(get_num_processes(), 0);
+
+    // without a foreground process the keys stay with the console
+    assert!(!push_key_event(InputEvent { event_type: EventType::Key, code: KEY_ENTER, value: 1 }));
+
+    let pid = run_program(&String::from("test_program12"), &Vec::new(), &Vec::new(), None, FdTable::new()).unwrap();
+    set_foreground_process(Some(pid));
+
+    // "hi", backspace, "ey", enter
+    for code in [0x23, 0x17, KEY_BACKSPACE, 0x12, 0x15, KEY_ENTER] {
+        assert!(push_key_event(InputEvent { event_type: EventType::Key, code, value: 1 }));
+        assert!(push_key_event(InputEvent { event_type: EventType::Key, code, value: 0 }));
+    }
+
+    assert!(wait_for_processes());
+
+    assert!(is_file(&String::from("key_test/result")));
+}
+
+#[kernel_test]
+fn test_stack_limit() {
+    store_test_program("test_program8", test_program!("test_program8"));
+
+    assert_eq!(get_num_processes(), 0);
+
+    let pid = start_spinner();
+    assert_eq!(get_stack_limit(pid), DEFAULT_STACK_LIMIT);
+
+    // the stack grows within the limit just below the stack pointer, never into the guard
+    let bottom = USER_STACK_END - DEFAULT_STACK_LIMIT;
+    assert!(grow_stack(pid, bottom, bottom + STACK_GROWTH_SLACK));
+    assert!(!grow_stack(pid, bottom, bottom + STACK_GROWTH_SLACK + 1));
+    assert!(!grow_stack(pid, bottom - 1, bottom));
+    assert!(!grow_stack(pid, USER_STACK_GUARD, USER_STACK_GUARD));
+
+    assert!(!set_stack_limit(pid, USER_STACK_SIZE - 1));
+    assert!(!set_stack_limit(pid, USER_STACK_MAX_SIZE + 1));
+    assert!(set_stack_limit(pid, USER_STACK_SIZE + 1));
+    assert_eq!(get_stack_limit(pid), USER_STACK_SIZE + PAGE_SIZE);
+    assert!(!grow_stack(pid, bottom, bottom));
+    assert!(set_stack_limit(pid, USER_STACK_MAX_SIZE));
+    assert!(grow_stack(pid, USER_STACK, USER_STACK));
+
+    assert!(kill_process(pid));
+    assert!(wait_for_processes());
+}
@@ -0,0 +1,75 @@
+use core::time::Duration;
+use kernel_std::DateTime;
+use kernel_test::{kernel_test, kernel_test_mod};
+use crate::rtc::{get_date_time, get_unix_time, get_unix_time_ns};
+use crate::timer::{get_ticks, get_timebase_frequency, now_ns, Instant};
+kernel_test_mod!(crate::tests::B1_timer);
+
+#[kernel_test]
+fn test_time_is_monotonic() {
+    let mut prev = now_ns();
+    for _ in 0..10000 {
+        let now = now_ns();
+        assert!(now >= prev);
+        prev = now;
+    }
+}
+
+#[kernel_test]
+fn test_ticks_are_milliseconds() {
+    assert!(get_timebase_frequency() >= 1000);
+
+    let ns = now_ns();
+    let ms = get_ticks();
+    assert!(ms >= ns / 1_000_000);
+    assert!(ms <= now_ns() / 1_000_000);
+}
+
+#[kernel_test]
+fn test_instant() {
+    let start = Instant::now();
+    let ms = get_ticks();
+    while get_ticks() < ms + 10 {}
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(9));
+    assert!(Instant::now() >= start);
+    assert_eq!(start.duration_since(Instant::now()), Duration::ZERO);
+}
+
+#[kernel_test]
+fn test_date_time_conversion() {
+    let dates = [
+        (0, DateTime { year: 1970, month: 1, day: 1, hour: 0, minute: 0, second: 0 }),
+        (68169600, DateTime { year: 1972, month: 2, day: 29, hour: 0, minute: 0, second: 0 }),
+        (951782400, DateTime { year: 2000, month: 2, day: 29, hour: 0, minute: 0, second: 0 }),
+        (1700000000, DateTime { year: 2023, month: 11, day: 14, hour: 22, minute: 13, second: 20 }),
+        (4102444799, DateTime { year: 2099, month: 12, day: 31, hour: 23, minute: 59, second: 59 }),
+    ];
+    for (secs, date) in dates {
+        assert_eq!(DateTime::from_unix_secs(secs), date);
+        assert_eq!(date.to_unix_secs(), secs);
+    }
+
+    let mut secs = 0;
+    while secs < 5_000_000_000 {
+        assert_eq!(DateTime::from_unix_secs(secs).to_unix_secs(), secs);
+        secs += 86399 * 7;
+    }
+}
+
+#[kernel_test]
+fn test_rtc() {
+    // qemu starts the clock at the time of the host
+    assert!(get_date_time().year >= 2024);
+
+    let start = get_unix_time_ns();
+    let ms = get_ticks();
+    while get_ticks() < ms + 10 {}
+    let elapsed = get_unix_time_ns() - start;
+    assert!((9_000_000..1_000_000_000).contains(&elapsed));
+
+    let ns = get_unix_time_ns();
+    let secs = get_unix_time();
+    assert!(secs >= ns / 1_000_000_000);
+    assert!(secs <= get_unix_time_ns() / 1_000_000_000);
+}
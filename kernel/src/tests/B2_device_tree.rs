@@ -0,0 +1,52 @@
+use kernel_test::{kernel_test, kernel_test_mod};
+use crate::boot::{get_hart_stacks_start, get_num_cores, MAX_CORES, STACK_SIZE};
+use crate::device_tree::{get_machine, parse_device_tree};
+use crate::memory::{get_memory_size, get_num_pages, KERNEL_OFFSET, PAGE_SIZE};
+use crate::scheduler::get_idle_ticks;
+use crate::virtio::definitions::{get_num_virtio_slots, get_virtio_id};
+kernel_test_mod!(crate::tests::B2_device_tree);
+
+#[kernel_test]
+fn test_machine_from_device_tree() {
+    let machine = get_machine();
+    assert!(machine.from_device_tree);
+    assert!(machine.num_harts >= get_num_cores());
+    // qemu virt lists it on /cpus
+    assert_eq!(machine.timebase_frequency, 10_000_000);
+    assert!(get_num_cores() >= 1 && get_num_cores() <= MAX_CORES);
+    assert!(get_memory_size() >= 64 * 1024 * 1024);
+    assert_eq!(get_num_pages() * PAGE_SIZE, get_memory_size());
+}
+
+#[kernel_test]
+fn test_per_core_data() {
+    assert_eq!(get_idle_ticks().size(), get_num_cores());
+
+    let stacks_end = KERNEL_OFFSET + get_memory_size();
+    assert_eq!(get_hart_stacks_start() % PAGE_SIZE, 0);
+    assert_eq!(stacks_end - get_hart_stacks_start(), (get_num_cores() - 1) as u64 * STACK_SIZE as u64);
+}
+
+#[kernel_test]
+fn test_virtio_slots() {
+    let machine = get_machine();
+    assert!(get_num_virtio_slots() > 0);
+    for id in 0..get_num_virtio_slots() {
+        let slot = machine.virtio_slots[id as usize];
+        assert_eq!(get_virtio_id(slot.irq), Some(id));
+        if id > 0 {
+            assert!(machine.virtio_slots[id as usize - 1].base < slot.base);
+        }
+    }
+    assert_eq!(get_virtio_id(0), None);
+}
+
+#[kernel_test]
+fn test_invalid_device_tree() {
+    let memory_size = get_memory_size();
+    let data = [0u32; 16];
+    assert!(!parse_device_tree(0));
+    assert!(!parse_device_tree(data.as_ptr() as u64));
+    assert!(get_machine().from_device_tree);
+    assert_eq!(get_memory_size(), memory_size);
+}
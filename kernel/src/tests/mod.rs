@@ -4,7 +4,8 @@ use kernel_std::{deserialize, print, println, serialize, Mutable, String, Vec};
 use crate::disk::disk::Disk;
 use kernel_std::bitset_size_bytes;
 use crate::print::{reset_print_color, set_print_color};
-use crate::timer::get_ticks;
+use core::time::Duration;
+use crate::timer::{get_ticks, Instant};
 use kernel_test::all_tests;
 use crate::disk::filesystem::{read_file, write_to_file};
 use crate::ROOT_MAGIC;
@@ -21,6 +22,8 @@ mod A7_disk;
 mod A8_memory_disk;
 mod A9_filesystem;
 mod B0_scheduler;
+mod B1_timer;
+mod B2_device_tree;
 
 pub trait KernelPerf {
     fn setup() -> Self;
@@ -155,17 +158,15 @@ fn run_perf_test<T: KernelPerf>(name: &str) {
     set_print_color(TextColor::LightCyan, TextColor::Black);
     print!(" {name}");
 
-    let mut total_ms = 0;
+    let mut total_time = Duration::ZERO;
     let mut iterations = 0;
-    let start_time = get_ticks();
+    let start_time = Instant::now();
     for _ in 0..PERF_TEST_MAX_ITERATIONS {
-        let start_ms = get_ticks();
+        let start = Instant::now();
         test_struct.run();
-        let end_ms = get_ticks();
-        // add ms
-        total_ms += end_ms - start_ms;
+        total_time += start.elapsed();
         iterations += 1;
-        if get_ticks() - start_time > 3000 {
+        if start_time.elapsed() > Duration::from_secs(3) {
             break;
         }
     }
@@ -180,7 +181,7 @@ fn run_perf_test<T: KernelPerf>(name: &str) {
         print!(" ");
     }
 
-    let perf_ms = total_ms as f64 / iterations as f64;
+    let perf_ms = total_time.as_nanos() as f64 / 1_000_000.0 / iterations as f64;
 
     let saved_perf_ms = get_perf_data(name);
 
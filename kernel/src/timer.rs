@@ -1,29 +1,68 @@
-use core::hint::black_box;
-use crate::boot::NUM_CORES;
-use crate::riscv::{get_mhartid, get_mie, get_mstatus, set_mie, set_mscratch, set_mstatus, set_mtvec, CLINT, MIE_TIMER, MSTATUS_MMI};
+use core::time::Duration;
+#[cfg(not(feature = "sbi"))]
+use crate::boot::MAX_CORES;
+use crate::device_tree::get_machine;
+use crate::scheduler::{block_process, wake_process};
+#[cfg(not(feature = "sbi"))]
+use crate::riscv::{get_mhartid, get_mie, get_mstatus, set_mie, set_mscratch, set_mstatus, set_mtvec, MIE_TIMER, MSTATUS_MMI};
+use crate::riscv::{get_sip, set_sip, SIP_SOFTWARE};
+#[cfg(feature = "sbi")]
+use crate::riscv::{get_time, SIP_TIMER};
+#[cfg(feature = "sbi")]
+use crate::sbi::sbi_set_timer;
+use kernel_std::{Mutable, Vec};
+#[cfg(not(feature = "sbi"))]
 use kernel_std::Lock;
-use core::ptr::{addr_of, write_volatile};
+use core::ptr::addr_of;
+#[cfg(not(feature = "sbi"))]
+use core::ptr::{read_volatile, write_volatile};
 
+#[cfg(not(feature = "sbi"))]
 extern "C" {
     fn timervec();
 }
 
+// how often the timer interrupt preempts the running process on every core
+const INTERRUPTS_PER_SECOND: u64 = 1000;
+
 // a scratch area per CPU for machine-mode timer interrupts.
+#[cfg(not(feature = "sbi"))]
 #[used]
-static mut TIMER_SCRATCH: [u64; NUM_CORES * 5] = [0; NUM_CORES * 5];
+static mut TIMER_SCRATCH: [u64; MAX_CORES * 5] = [0; MAX_CORES * 5];
+#[cfg(not(feature = "sbi"))]
 static TIMER_LOCK: Lock = Lock::new();
 
+// how many times per second mtime counts, from the device tree or 10 MHz as on the qemu virt machine
+pub fn get_timebase_frequency() -> u64 {
+    get_machine().timebase_frequency
+}
+
+fn get_timer_interval() -> u64 {
+    get_timebase_frequency() / INTERRUPTS_PER_SECOND
+}
+
+// the registers of the core local interrupter
+#[cfg(not(feature = "sbi"))]
+fn get_mtimecmp_addr() -> u64 {
+    get_machine().clint_base + 0x4000 + 8 * get_mhartid()
+}
+
+#[cfg(not(feature = "sbi"))]
+fn get_mtime_addr() -> u64 {
+    get_machine().clint_base + 0xBFF8
+}
+
+#[cfg(not(feature = "sbi"))]
 pub fn machine_mode_timer_init() {
     TIMER_LOCK.spinlock();
 
-    let frequency = 100;
-    let interval = 1193180 / frequency;
+    let interval = get_timer_interval();
     unsafe {
-        write_volatile((CLINT + 0x4000 + 8 * get_mhartid()) as *mut u64, *((CLINT + 0xBFF8) as *mut u64) + interval);
+        write_volatile(get_mtimecmp_addr() as *mut u64, get_mtime() + interval);
     }
 
     unsafe {
-        TIMER_SCRATCH[(5 * get_mhartid() + 3) as usize] = CLINT + 0x4000 + 8 * get_mhartid();
+        TIMER_SCRATCH[(5 * get_mhartid() + 3) as usize] = get_mtimecmp_addr();
         TIMER_SCRATCH[(5 * get_mhartid() + 4) as usize] = interval;
 
         set_mscratch(addr_of!(TIMER_SCRATCH[(5 * get_mhartid()) as usize]) as u64);
@@ -43,15 +82,99 @@ pub fn machine_mode_timer_init() {
     TIMER_LOCK.unlock();
 }
 
-static mut TICKS: u64 = 0;
+// under the firmware the supervisor timer interrupt comes directly, every core arms its own
+#[cfg(feature = "sbi")]
+pub fn supervisor_timer_init() {
+    sbi_set_timer(get_mtime() + get_timer_interval());
+}
 
-// this is called every tick on core 0
-pub fn tick() {
-    unsafe {
-        TICKS += 1;
+// timervec turns the machine timer interrupt into a software interrupt, under the firmware the timer interrupt
+// stays pending until the next one is set. a software interrupt may also be an ipi waking an idle core
+pub fn acknowledge_timer_interrupt() {
+    set_sip(get_sip() & !SIP_SOFTWARE);
+
+    #[cfg(feature = "sbi")]
+    if get_sip() & SIP_TIMER != 0 {
+        sbi_set_timer(get_mtime() + get_timer_interval());
     }
 }
 
+// the same counter on every core, it only goes forward
+#[cfg(not(feature = "sbi"))]
+fn get_mtime() -> u64 {
+    unsafe { read_volatile(get_mtime_addr() as *const u64) }
+}
+
+// the firmware keeps the clint to itself, the time csr reads the same counter
+#[cfg(feature = "sbi")]
+fn get_mtime() -> u64 {
+    get_time()
+}
+
+// nanoseconds since the machine started
+pub fn now_ns() -> u64 {
+    (get_mtime() as u128 * 1_000_000_000 / get_timebase_frequency() as u128) as u64
+}
+
+// milliseconds since the machine started, all deadlines and cpu times in the kernel are kept in these
 pub fn get_ticks() -> u64 {
-    unsafe { black_box(TICKS) }
+    (get_mtime() as u128 * 1000 / get_timebase_frequency() as u128) as u64
+}
+
+// a point in monotonic time
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Instant {
+    ns: u64,
+}
+
+impl Instant {
+    pub fn now() -> Self {
+        Self { ns: now_ns() }
+    }
+
+    pub fn elapsed(self) -> Duration {
+        Self::now().duration_since(self)
+    }
+
+    // zero if earlier is not before self
+    pub const fn duration_since(self, earlier: Self) -> Duration {
+        Duration::from_nanos(self.ns.saturating_sub(earlier.ns))
+    }
+}
+
+// (until, pid) of sleeping processes ordered by until, so only the first entries have to be checked
+static SLEEPERS: Mutable<Option<Vec<(u64, usize)>>> = Mutable::new(None);
+
+fn get_sleep_channel() -> usize {
+    addr_of!(SLEEPERS) as usize
+}
+
+// blocks the process until get_ticks() is greater or equal than until
+pub fn sleep_until(pid: usize, until: u64) {
+    let t = SLEEPERS.borrow();
+    let sleepers = SLEEPERS.get_mut(&t).get_or_insert_with(Vec::new);
+    // a killed process may have left an entry with its pid behind
+    sleepers.retain(&|(_, sleeper)| *sleeper != pid);
+    sleepers.push((until, pid));
+    let mut i = sleepers.size() - 1;
+    while i > 0 && sleepers[i - 1].0 > until {
+        sleepers[i] = sleepers[i - 1];
+        i -= 1;
+    }
+    sleepers[i] = (until, pid);
+    block_process(pid, get_sleep_channel());
+    SLEEPERS.release(t);
+}
+
+// called by the scheduler, only one core at a time goes through the sleepers
+pub fn wake_sleepers() {
+    let Some(t) = SLEEPERS.try_borrow() else {
+        return;
+    };
+    if let Some(sleepers) = SLEEPERS.get_mut(&t) {
+        while sleepers.size() > 0 && sleepers[0].0 <= get_ticks() {
+            wake_process(sleepers.remove(0).1, get_sleep_channel());
+        }
+    }
+    SLEEPERS.release(t);
 }
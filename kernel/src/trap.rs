@@ -1,12 +1,18 @@
 use core::arch::global_asm;
-use crate::riscv::{get_core_id, get_scause, get_sepc, get_sip, get_sstatus, get_stval, interrupts_enable, interrupts_get, set_sip, set_sstatus, set_stvec, SSTATUS_SPP, SSTATUS_UIE};
-use crate::timer::{get_ticks, tick};
-use kernel_std::{debug_str, debugln, print, println};
+use crate::riscv::{get_scause, get_sepc, get_sstatus, get_stval, interrupts_enable, interrupts_get, set_sstatus, set_stvec, SSTATUS_SPP, SSTATUS_UIE};
+use crate::timer::{acknowledge_timer_interrupt, get_ticks, now_ns, sleep_until};
+use kernel_std::{debug_str, debugln, print, println, String, Vec};
 use crate::input::virtio_input_irq;
-use crate::memory::{free_page, map_page_auto, switch_to_page_table};
+use crate::memory::{copy_str_from_user, copy_to_user, fault_in_page, get_current_page_table, is_page_mapped, is_page_reserved, is_user_addr, refresh_paging, reserve_page, switch_to_page_table, take_user_page, release_page, user_virt_to_phys, PageAccess, PAGE_SIZE, USER_STACK, USER_STACK_END, USER_STACK_GUARD, USER_STACK_MAX_SIZE, USER_THREADS, USER_THREADS_END};
 use crate::plic::{plic_complete, plic_irq};
 use crate::print::check_screen_refresh_for_print;
-use crate::scheduler::{get_context, get_cpu_data, mark_process_ready, put_process_to_sleep, refresh_paging_for_proc, scheduler, scheduler_next_proc, terminate_process};
+use crate::fd_table::{FdTable, IoStatus};
+use crate::futex::{futex_wait, futex_wake, FutexStatus, FUTEX_TABLE_FULL};
+use crate::pipe::{wait_for_pipe_read, wait_for_pipe_write};
+use crate::power::{reboot, shutdown};
+use crate::rtc::get_unix_time_ns;
+use crate::keyboard::{key_event_char, read_key, wait_for_key, KeyStatus};
+use crate::scheduler::{account_cpu_time, collect_child, create_thread, fork_process, get_context, get_stack_limit, grow_stack, set_stack_limit, STACK_GROWTH_SLACK, get_cpu_data, get_thread_owner, is_process_killed, is_started_by_kernel, kill_process, may_control, mark_process_ready, release_replaced_pages, shoot_down_tlb, run_program, scheduler, set_affinity, set_nice, terminate_process, wait_for_child, with_fd_table, with_page_table, ChildStatus, KILLED_EXIT_CODE};
 use crate::virtio::device::virtio_irq;
 
 global_asm!(include_str!("asm/kernelvec.S"));
@@ -26,17 +32,7 @@ extern "C" fn kerneltrap() {
 
     match ty {
         InterruptType::Timer => {
-            if get_core_id() == 0 {
-                tick();
-            }
-
-            // acknowledge the software interrupt by clearing
-            // the SSIP bit in sip.
-            let mut sip = get_sip();
-            sip &= !2;
-            set_sip(sip);
-
-            scheduler_next_proc();
+            acknowledge_timer_interrupt();
         }
         InterruptType::OtherDevice => {
             let irq = plic_irq();
@@ -46,7 +42,7 @@ extern "C" fn kerneltrap() {
 
             plic_complete(irq);
         }
-        InterruptType::Unknown | InterruptType::User => {
+        InterruptType::Unknown | InterruptType::User | InterruptType::PageFault(_) => {
             println!("Interrupt occurred");
             println!("Scause: {}", get_scause());
             println!("Sepc: 0x{:x}", get_sepc());
@@ -61,6 +57,7 @@ enum InterruptType {
     Timer,
     User,
     OtherDevice,
+    PageFault(PageAccess),
 }
 
 fn get_interrupt_type() -> InterruptType {
@@ -68,10 +65,17 @@ fn get_interrupt_type() -> InterruptType {
 
     if (scause & 0x8000000000000000) != 0 && (scause & 0xff) == 9 {
         InterruptType::OtherDevice
-    } else if scause == 0x8000000000000001 {
+    } else if scause == 0x8000000000000001 || scause == 0x8000000000000005 {
+        // a software interrupt from timervec or an ipi, or the timer interrupt under the firmware
         InterruptType::Timer
     } else if scause == 8 {
         InterruptType::User
+    } else if scause == 12 {
+        InterruptType::PageFault(PageAccess::Execute)
+    } else if scause == 13 {
+        InterruptType::PageFault(PageAccess::Read)
+    } else if scause == 15 {
+        InterruptType::PageFault(PageAccess::Write)
     } else {
         InterruptType::Unknown
     }
@@ -95,16 +99,7 @@ extern "C" fn usertrap() -> ! {
 
     match ty {
         InterruptType::Timer => {
-            if get_core_id() == 0 {
-                tick();
-            }
-            // acknowledge the software interrupt by clearing
-            // the SSIP bit in sip.
-            let mut sip = get_sip();
-            sip &= !2;
-            set_sip(sip);
-
-            scheduler_next_proc();
+            acknowledge_timer_interrupt();
         }
         InterruptType::OtherDevice => {
             let irq = plic_irq();
@@ -118,6 +113,9 @@ extern "C" fn usertrap() -> ! {
             get_context().pc += 4;
             get_cpu_data().was_last_interrupt_external = true;
         }
+        InterruptType::PageFault(access) => {
+            user_page_fault(access);
+        }
         InterruptType::Unknown => {
             debug_str("Interrupt occurred");
             println!("Interrupt occurred");
@@ -141,60 +139,431 @@ extern "C" fn usertrap() -> ! {
     sched_resume()
 }
 
-fn sched_resume() -> ! {
-    if get_cpu_data().was_last_interrupt_external {
-        let int_code = get_context().a7;
-        match int_code {
-            1 => {
-                // print str
-                let arg1 = get_context().a3 as *mut u8;
-                let arg2 = get_context().a4;
-                // convert to &str
-                let arg1 = unsafe { core::str::from_utf8_unchecked(core::slice::from_raw_parts(arg1, arg2 as usize)) };
-
-                print!("{}", arg1);
-                mark_process_ready(get_cpu_data().last_pid);
-            }
-            2 => {
-                // get ticks
-                get_context().a2 = get_ticks();
-                mark_process_ready(get_cpu_data().last_pid);
+// the faulting instruction runs again once the reserved page is mapped, any other fault ends the whole process.
+// the stack of the main thread grows on faults just below its stack pointer until it reaches the limit
+fn user_page_fault(access: PageAccess) {
+    let pid = get_cpu_data().last_pid;
+    let addr = get_stval();
+    let sp = get_context().sp;
+    let in_stack = (USER_STACK_GUARD..USER_STACK_END).contains(&addr);
+    if fault_in_page(get_current_page_table(), addr, access) || (in_stack && grow_stack(pid, addr, sp) && fault_in_page(get_current_page_table(), addr, access)) {
+        // the tlb may still hold the entry from before the page was mapped
+        refresh_paging();
+        return;
+    }
+
+    let limit = get_stack_limit(pid);
+    if in_stack && addr < sp.saturating_sub(STACK_GROWTH_SLACK) {
+        println!("Process {} killed: {} page fault at 0x{:x} below the stack pointer 0x{:x} (pc 0x{:x})", pid, access.name(), addr, sp, get_context().pc);
+    } else if in_stack && addr < USER_STACK {
+        println!("Process {} killed: stack overflow at 0x{:x}, the stack can not grow beyond the maximum of {} kB (pc 0x{:x})", pid, addr, USER_STACK_MAX_SIZE / 1024, get_context().pc);
+    } else if in_stack && addr < USER_STACK_END - limit {
+        println!("Process {} killed: stack overflow at 0x{:x}, the stack limit of the process is {} kB (pc 0x{:x})", pid, addr, limit / 1024, get_context().pc);
+    } else {
+        println!("Process {} killed: {} page fault at 0x{:x} (pc 0x{:x})", pid, access.name(), addr, get_context().pc);
+    }
+    kill_process(pid);
+    kill_process(get_thread_owner(pid));
+}
+
+// the spawn block holds the path followed by arguments prefixed with 'A' and
+// environment variables prefixed with 'E' in the form KEY=VALUE, each terminated by '\0'
+fn parse_spawn_block(block: &String) -> Option<(String, Vec<String>, Vec<(String, String)>)> {
+    let mut entries = block.split('\0');
+    // everything is terminated, so the last entry is empty
+    if entries.pop()?.size() != 0 || entries.size() == 0 {
+        return None;
+    }
+
+    let mut args = Vec::new();
+    let mut env = Vec::new();
+    for entry in (&entries).into_iter().skip(1) {
+        let mut value = String::new();
+        for c in entry.into_iter().skip(1) {
+            value.push(*c);
+        }
+
+        match entry.get(0)? {
+            'A' => {
+                args.push(value);
             }
-            3 => {
-                // get pid
-                get_context().a2 = get_cpu_data().last_pid as u64;
-                mark_process_ready(get_cpu_data().last_pid);
+            'E' => {
+                let mut key = String::new();
+                let mut chars = (&value).into_iter();
+                for c in &mut chars {
+                    if *c == '=' {
+                        break;
+                    }
+                    key.push(*c);
+                }
+                let mut val = String::new();
+                for c in chars {
+                    val.push(*c);
+                }
+                env.push((key, val));
             }
-            4 => {
-                // exit process
-                terminate_process(get_cpu_data().last_pid);
+            _ => return None,
+        }
+    }
+
+    Some((entries[0].clone(), args, env))
+}
+
+// returned in a2 when a syscall fails
+const SYSCALL_ERROR: u64 = u64::MAX;
+
+fn sys_print_str() {
+    let arg1 = get_context().a3;
+    let arg2 = get_context().a4;
+
+    if let Some(s) = copy_str_from_user(get_current_page_table(), arg1, arg2 as usize) {
+        print!("{}", s);
+        get_context().a2 = 0;
+    } else {
+        get_context().a2 = SYSCALL_ERROR;
+    }
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+fn sys_get_ticks() {
+    get_context().a2 = get_ticks();
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+fn sys_get_pid() {
+    get_context().a2 = get_cpu_data().last_pid as u64;
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+fn sys_exit() {
+    let exit_code = get_context().a3 as i32;
+    terminate_process(get_cpu_data().last_pid, exit_code);
+}
+
+fn sys_alloc_page() {
+    let addr = get_context().a3;
+    let ignore_if_exists = get_context().a4 != 0;
+    with_page_table(get_cpu_data().last_pid, &mut || {
+        let exists = user_virt_to_phys(get_current_page_table(), addr, false).is_some() || is_page_reserved(get_current_page_table(), addr);
+        // thread stacks and pages only the kernel can access are not given out
+        let reserved = (USER_THREADS..USER_THREADS_END).contains(&addr) || (!exists && is_page_mapped(get_current_page_table(), addr));
+        if !is_user_addr(addr) || reserved || (exists && !ignore_if_exists) {
+            get_context().a2 = SYSCALL_ERROR;
+        } else {
+            // the page gets its memory when it is first accessed
+            if !exists {
+                reserve_page(addr as *mut u8, true, true, false);
             }
-            5 => {
-                // Alloc page
-                let addr = get_context().a3 as *mut u8;
-                let ignore_if_exists = get_context().a4 != 0;
-                map_page_auto(addr, ignore_if_exists, true, true, false);
-                mark_process_ready(get_cpu_data().last_pid);
+            get_context().a2 = 0;
+        }
+    });
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+fn sys_dealloc_page() {
+    let addr = get_context().a3 / PAGE_SIZE * PAGE_SIZE;
+    let mut taken = Vec::new();
+    if with_page_table(get_cpu_data().last_pid, &mut || take_user_page(get_current_page_table(), addr, &mut taken)) {
+        // other threads may still access the page until their cores have flushed their tlb
+        shoot_down_tlb(get_cpu_data().last_pid);
+        for page in taken {
+            release_page(page);
+        }
+        get_context().a2 = 0;
+    } else {
+        get_context().a2 = SYSCALL_ERROR;
+    }
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+fn sys_sleep() {
+    let until = get_ticks() + get_context().a3;
+    sleep_until(get_cpu_data().last_pid, until);
+}
+
+fn sys_spawn() {
+    let block = copy_str_from_user(get_current_page_table(), get_context().a3, get_context().a4 as usize);
+    let fd_table = with_fd_table(get_cpu_data().last_pid, &mut |fd_table| FdTable::inherit(fd_table, get_current_page_table(), get_context().a5, get_context().a6 as usize));
+
+    let pid = match (block.as_ref().and_then(parse_spawn_block), fd_table) {
+        (Some((path, args, env)), Some(fd_table)) => run_program(&path, &args, &env, Some(get_cpu_data().last_pid), fd_table).ok(),
+        _ => None,
+    };
+    get_context().a2 = pid.map_or(SYSCALL_ERROR, |pid| pid as u64);
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+fn sys_wait() {
+    let child_pid = get_context().a3 as usize;
+    match collect_child(get_cpu_data().last_pid, child_pid) {
+        ChildStatus::Exited(exit_code) => {
+            get_context().a2 = exit_code as u32 as u64;
+            mark_process_ready(get_cpu_data().last_pid);
+        }
+        ChildStatus::Running => {
+            // the ecall is executed again once the child has exited
+            get_context().pc -= 4;
+            wait_for_child(get_cpu_data().last_pid, child_pid);
+        }
+        ChildStatus::NotChild => {
+            get_context().a2 = SYSCALL_ERROR;
+            mark_process_ready(get_cpu_data().last_pid);
+        }
+    }
+}
+
+fn sys_open() {
+    let path = copy_str_from_user(get_current_page_table(), get_context().a3, get_context().a4 as usize);
+    let create = get_context().a5 != 0;
+    let fd = path.and_then(|path| with_fd_table(get_cpu_data().last_pid, &mut |fd_table| fd_table.open(&path, create)));
+    get_context().a2 = fd.map_or(SYSCALL_ERROR, |fd| fd as u64);
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+fn sys_read() {
+    let fd = get_context().a3 as usize;
+    match with_fd_table(get_cpu_data().last_pid, &mut |fd_table| fd_table.read(fd, get_current_page_table(), get_context().a4, get_context().a5 as usize)) {
+        IoStatus::Done(size) => {
+            get_context().a2 = size as u64;
+            mark_process_ready(get_cpu_data().last_pid);
+        }
+        IoStatus::Blocked(pipe) => {
+            // the ecall is executed again once the pipe has data
+            get_context().pc -= 4;
+            wait_for_pipe_read(get_cpu_data().last_pid, pipe);
+        }
+        IoStatus::Failed => {
+            get_context().a2 = SYSCALL_ERROR;
+            mark_process_ready(get_cpu_data().last_pid);
+        }
+    }
+}
+
+fn sys_write() {
+    let fd = get_context().a3 as usize;
+    match with_fd_table(get_cpu_data().last_pid, &mut |fd_table| fd_table.write(fd, get_current_page_table(), get_context().a4, get_context().a5 as usize)) {
+        IoStatus::Done(size) => {
+            get_context().a2 = size as u64;
+            mark_process_ready(get_cpu_data().last_pid);
+        }
+        IoStatus::Blocked(pipe) => {
+            // the ecall is executed again once the pipe has space
+            get_context().pc -= 4;
+            wait_for_pipe_write(get_cpu_data().last_pid, pipe);
+        }
+        IoStatus::Failed => {
+            get_context().a2 = SYSCALL_ERROR;
+            mark_process_ready(get_cpu_data().last_pid);
+        }
+    }
+}
+
+fn sys_seek() {
+    let fd = get_context().a3 as usize;
+    let res = with_fd_table(get_cpu_data().last_pid, &mut |fd_table| fd_table.seek(fd, get_context().a4 as usize));
+    get_context().a2 = res.map_or(SYSCALL_ERROR, |offset| offset as u64);
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+fn sys_close() {
+    let fd = get_context().a3 as usize;
+    let closed = with_fd_table(get_cpu_data().last_pid, &mut |fd_table| fd_table.close(fd));
+    get_context().a2 = if closed { 0 } else { SYSCALL_ERROR };
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+fn sys_pipe() {
+    let fds_addr = get_context().a3;
+    with_fd_table(get_cpu_data().last_pid, &mut |fd_table| {
+        if let Some((read_fd, write_fd)) = fd_table.pipe() {
+            let mut fds = [0u8; 16];
+            fds[..8].copy_from_slice(&(read_fd as u64).to_le_bytes());
+            fds[8..].copy_from_slice(&(write_fd as u64).to_le_bytes());
+            if copy_to_user(get_current_page_table(), fds_addr, &fds) {
+                get_context().a2 = 0;
+            } else {
+                fd_table.close(read_fd);
+                fd_table.close(write_fd);
+                get_context().a2 = SYSCALL_ERROR;
             }
-            6 => {
-                // Dealloc page
-                let addr = get_context().a3 as *mut u8;
-                free_page(addr as u64);
-                refresh_paging_for_proc(get_cpu_data().last_pid);
+        } else {
+            get_context().a2 = SYSCALL_ERROR;
+        }
+    });
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+fn sys_kill() {
+    let target = get_context().a3 as usize;
+    let killed = may_control(get_cpu_data().last_pid, target) && kill_process(target);
+    get_context().a2 = if killed { 0 } else { SYSCALL_ERROR };
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+fn sys_create_thread() {
+    let tid = create_thread(get_cpu_data().last_pid, get_context().a3, get_context().a4, get_context().a5);
+    get_context().a2 = tid.map_or(SYSCALL_ERROR, |tid| tid as u64);
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+fn sys_futex_wait() {
+    let addr = get_context().a3;
+    let expected = get_context().a4 as u32;
+    // the return value is set up front, the process continues after the ecall once woken
+    get_context().a2 = 0;
+    match futex_wait(get_cpu_data().last_pid, get_current_page_table(), addr, expected) {
+        FutexStatus::Blocked => {}
+        FutexStatus::ValueChanged => mark_process_ready(get_cpu_data().last_pid),
+        FutexStatus::TableFull => {
+            get_context().a2 = FUTEX_TABLE_FULL;
+            mark_process_ready(get_cpu_data().last_pid);
+        }
+        FutexStatus::Failed => {
+            get_context().a2 = SYSCALL_ERROR;
+            mark_process_ready(get_cpu_data().last_pid);
+        }
+    }
+}
+
+fn sys_futex_wake() {
+    let woken = futex_wake(get_current_page_table(), get_context().a3, get_context().a4 as usize);
+    get_context().a2 = woken.map_or(SYSCALL_ERROR, |woken| woken as u64);
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+fn sys_set_nice() {
+    let pid = get_context().a3 as usize;
+    let nice = get_context().a4 as i64;
+    let res = may_control(get_cpu_data().last_pid, pid) && i32::try_from(nice).is_ok_and(|nice| set_nice(pid, nice));
+    get_context().a2 = if res { 0 } else { SYSCALL_ERROR };
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+fn sys_set_affinity() {
+    let pid = get_context().a3 as usize;
+    let affinity = get_context().a4;
+    let res = may_control(get_cpu_data().last_pid, pid) && set_affinity(pid, affinity);
+    get_context().a2 = if res { 0 } else { SYSCALL_ERROR };
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+fn sys_read_key() {
+    let pid = get_cpu_data().last_pid;
+    let owner = get_thread_owner(pid);
+    let blocking = get_context().a3 != 0;
+    match read_key(owner) {
+        KeyStatus::Event(event) => {
+            // the value is in the low 32 bits, then the key code and the character, 0 means no event
+            let c = key_event_char(event).map_or(0, |c| c as u64);
+            get_context().a2 = (c << 48) | ((event.code as u64) << 32) | event.value as u32 as u64;
+            mark_process_ready(pid);
+        }
+        KeyStatus::Empty | KeyStatus::NotForeground if blocking => {
+            // the ecall is executed again once there is a key for the process
+            get_context().pc -= 4;
+            wait_for_key(pid, owner);
+        }
+        KeyStatus::Empty => {
+            get_context().a2 = 0;
+            mark_process_ready(pid);
+        }
+        KeyStatus::NotForeground => {
+            get_context().a2 = SYSCALL_ERROR;
+            mark_process_ready(pid);
+        }
+    }
+}
+
+// get time in nanoseconds
+fn sys_get_time() {
+    get_context().a2 = now_ns();
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+// get wall-clock time in nanoseconds since 1970
+fn sys_get_wall_time() {
+    get_context().a2 = get_unix_time_ns();
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+// only processes started by the kernel may turn off the machine
+fn sys_shutdown() {
+    if !is_started_by_kernel(get_cpu_data().last_pid) {
+        get_context().a2 = SYSCALL_ERROR;
+        mark_process_ready(get_cpu_data().last_pid);
+        return;
+    }
+    shutdown(get_context().a3 as u32);
+}
+
+fn sys_reboot() {
+    if !is_started_by_kernel(get_cpu_data().last_pid) {
+        get_context().a2 = SYSCALL_ERROR;
+        mark_process_ready(get_cpu_data().last_pid);
+        return;
+    }
+    reboot();
+}
+
+fn sys_fork() {
+    let child = fork_process(get_cpu_data().last_pid);
+    get_context().a2 = child.map_or(SYSCALL_ERROR, |child| child as u64);
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+fn sys_set_stack_limit() {
+    let set = set_stack_limit(get_cpu_data().last_pid, get_context().a3);
+    get_context().a2 = if set { 0 } else { SYSCALL_ERROR };
+    mark_process_ready(get_cpu_data().last_pid);
+}
+
+fn sched_resume() -> ! {
+    let owner = get_thread_owner(get_cpu_data().last_pid);
+    account_cpu_time(get_cpu_data().last_pid);
+
+    if is_process_killed(get_cpu_data().last_pid) {
+        terminate_process(get_cpu_data().last_pid, KILLED_EXIT_CODE);
+    } else if get_cpu_data().was_last_interrupt_external {
+        match get_context().a7 {
+            1 => sys_print_str(),
+            2 => sys_get_ticks(),
+            3 => sys_get_pid(),
+            4 => sys_exit(),
+            5 => sys_alloc_page(),
+            6 => sys_dealloc_page(),
+            7 => sys_sleep(),
+            8 => sys_spawn(),
+            9 => sys_wait(),
+            10 => sys_open(),
+            11 => sys_read(),
+            12 => sys_write(),
+            13 => sys_seek(),
+            14 => sys_close(),
+            15 => sys_pipe(),
+            16 => sys_kill(),
+            17 => sys_create_thread(),
+            18 => sys_futex_wait(),
+            19 => sys_futex_wake(),
+            20 => sys_set_nice(),
+            21 => sys_set_affinity(),
+            22 => sys_read_key(),
+            23 => sys_get_time(),
+            24 => sys_get_wall_time(),
+            25 => sys_shutdown(),
+            26 => sys_reboot(),
+            27 => sys_fork(),
+            28 => sys_set_stack_limit(),
+            code => {
+                println!("Unknown user interrupt occurred with code {}", code);
+                get_context().a2 = SYSCALL_ERROR;
                 mark_process_ready(get_cpu_data().last_pid);
             }
-            7 => {
-                // Sleep
-                let until = get_ticks() + get_context().a3;
-                put_process_to_sleep(get_cpu_data().last_pid, until);
-            }
-            _ => {
-                println!("Unknown user interrupt occurred with code {}", int_code);
-            }
         }
     } else {
         mark_process_ready(get_cpu_data().last_pid);
     }
+    // the fault or syscall may have broken copy-on-write, the thread only continues once the old pages are released
+    release_replaced_pages(owner);
     check_screen_refresh_for_print();
     scheduler()
 }
@@ -1,10 +1,10 @@
+use crate::device_tree::get_machine;
+
+// the most virtio-mmio slots the kernel uses, the id of a device is the index of its slot
 pub const MAX_VIRTIO_ID: u64 = 8;
 
 pub const VIRTIO_MAGIC: u32 = 0x74726976;
 
-// virtio mmio control registers, mapped starting at 0x10000000 + 0x1000 * id.
-pub const VIRTIO_MMIO_BASE: u64 = 0x10001000;
-
 #[allow(dead_code)]
 pub enum MmioOffset {
     MagicValue = 0x000,
@@ -33,8 +33,20 @@ pub enum MmioOffset {
     Config = 0x100,
 }
 
-pub const fn virtio_reg_addr(id: u64, reg: MmioOffset) -> *mut u8 {
-    let addr = VIRTIO_MMIO_BASE + 0x1000 * id + reg as u64;
+// the slots the device tree lists, ordered by address
+pub fn get_num_virtio_slots() -> u64 {
+    get_machine().num_virtio_slots as u64
+}
+
+// the id of the device that raises the interrupt
+pub fn get_virtio_id(irq: u32) -> Option<u64> {
+    let machine = get_machine();
+    (0..machine.num_virtio_slots).find(|id| machine.virtio_slots[*id].irq == irq).map(|id| id as u64)
+}
+
+// virtio mmio control registers of the slot
+pub fn virtio_reg_addr(id: u64, reg: MmioOffset) -> *mut u8 {
+    let addr = get_machine().virtio_slots[id as usize].base + reg as u64;
     addr as *mut u8
 }
 
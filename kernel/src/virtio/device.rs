@@ -6,8 +6,10 @@ use core::sync::atomic::{fence, Ordering};
 use crate::memory::{alloc_page, virt_to_phys, VirtAddr, PAGE_SIZE};
 use crate::riscv::get_core_id;
 use crate::spinlock::KernelLock;
-use crate::timer::get_ticks;
-use crate::virtio::definitions::{virtio_reg_addr, virtio_reg_read, virtio_reg_write, MmioOffset, VirtqAvail, VirtqDesc, VirtqUsed, MAX_VIRTIO_ID, NUM, VIRTIO_CONFIG_S_ACKNOWLEDGE, VIRTIO_CONFIG_S_DRIVER, VIRTIO_CONFIG_S_DRIVER_OK, VIRTIO_CONFIG_S_FEATURES_OK, VIRTIO_F_ANY_LAYOUT, VIRTIO_MAGIC, VIRTIO_RING_F_EVENT_IDX, VIRTIO_RING_F_INDIRECT_DESC, VRING_DESC_F_NEXT, VRING_DESC_F_WRITE};
+use core::time::Duration;
+use crate::timer::Instant;
+use crate::device_tree::get_machine;
+use crate::virtio::definitions::{get_virtio_id, virtio_reg_addr, virtio_reg_read, virtio_reg_write, MmioOffset, VirtqAvail, VirtqDesc, VirtqUsed, MAX_VIRTIO_ID, NUM, VIRTIO_CONFIG_S_ACKNOWLEDGE, VIRTIO_CONFIG_S_DRIVER, VIRTIO_CONFIG_S_DRIVER_OK, VIRTIO_CONFIG_S_FEATURES_OK, VIRTIO_F_ANY_LAYOUT, VIRTIO_MAGIC, VIRTIO_RING_F_EVENT_IDX, VIRTIO_RING_F_INDIRECT_DESC, VRING_DESC_F_NEXT, VRING_DESC_F_WRITE};
 
 pub struct VirtioDevice {
     // three virtqueues
@@ -112,7 +114,7 @@ impl VirtioDevice {
         Some(device)
     }
 
-    pub const fn get_config_address(&self) -> *mut u8 {
+    pub fn get_config_address(&self) -> *mut u8 {
         virtio_reg_addr(self.virtio_id, MmioOffset::Config)
     }
 
@@ -212,15 +214,15 @@ impl VirtioDevice {
 
         self.lock.unlock();
 
-        let start_time = get_ticks();
+        let start_time = Instant::now();
 
         if self.irq_waiting {
-            virtio_irq(self.virtio_id as u32 + 1);
+            virtio_irq(get_machine().virtio_slots[self.virtio_id as usize].irq);
             self.irq_waiting = false;
         }
 
         while unsafe { read_volatile(&self.info[idx]) } {
-            assert!(get_ticks() - start_time < 3000, "virtio_send timeout");
+            assert!(start_time.elapsed() < Duration::from_secs(3), "virtio_send timeout");
 
             self.irq_waiting = black_box(self.irq_waiting);
             unsafe {
@@ -339,10 +341,9 @@ impl VirtioDevice {
 }
 
 pub fn virtio_irq(irq: u32) {
-    if irq == 0 || irq > MAX_VIRTIO_ID as u32 {
+    let Some(id) = get_virtio_id(irq) else {
         return;
-    }
-    let id = irq as u64 - 1;
+    };
 
     if let Some(device) = unsafe { DEVICES[id as usize].as_mut() } {
         if device.lock.locked_by() == get_core_id() as i32 {
@@ -0,0 +1,74 @@
+use kernel_std::{Mutable, Vec};
+use crate::scheduler::{block_process, mark_process_ready, wake_process};
+
+// processes blocked until some other part of the kernel wakes them, the address of the queue identifies it
+pub struct WaitQueue {
+    waiters: Mutable<Option<Vec<usize>>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self { waiters: Mutable::new(None) }
+    }
+
+    fn get_channel(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    // blocks the process until it is woken up after condition became true, the process is only marked ready
+    // if it is true already. the condition is checked under the lock of the queue, so a wake in between is not lost,
+    // which also means that the waker must not hold a lock the condition needs
+    pub fn wait_until(&self, pid: usize, condition: &dyn Fn() -> bool) {
+        let t = self.waiters.borrow();
+        if condition() {
+            mark_process_ready(pid);
+        } else {
+            let waiters = self.waiters.get_mut(&t).get_or_insert_with(Vec::new);
+            // a killed process may have left an entry with its pid behind
+            waiters.retain(&|waiter| *waiter != pid);
+            waiters.push(pid);
+            block_process(pid, self.get_channel());
+        }
+        self.waiters.release(t);
+    }
+
+    // every waiter runs again and has to check itself whether it can continue
+    pub fn wake_all(&self) {
+        let t = self.waiters.borrow();
+        let waiters = self.waiters.get_mut(&t).take();
+        self.waiters.release(t);
+
+        for pid in waiters.into_iter().flatten() {
+            wake_process(pid, self.get_channel());
+        }
+    }
+
+    // for wakers holding the lock of a process slot, the shared lock of a process or a lock the condition needs
+    pub fn wake_all_later(&'static self) {
+        let t = DEFERRED_WAKES.borrow();
+        let queues = DEFERRED_WAKES.get_mut(&t).get_or_insert_with(Vec::new);
+        if !queues.into_iter().any(|queue| core::ptr::eq(*queue, self)) {
+            queues.push(self);
+        }
+        DEFERRED_WAKES.release(t);
+    }
+}
+
+// queues woken by code that held locks a wake can not be nested in, the scheduler wakes their processes
+static DEFERRED_WAKES: Mutable<Option<Vec<&'static WaitQueue>>> = Mutable::new(None);
+
+// called by the scheduler without any locks held, a core that finds another one at it just goes on
+pub fn wake_deferred() {
+    loop {
+        let Some(t) = DEFERRED_WAKES.try_borrow() else {
+            return;
+        };
+        let queue = DEFERRED_WAKES.get_mut(&t).as_mut().and_then(Vec::pop);
+        DEFERRED_WAKES.release(t);
+
+        let Some(queue) = queue else {
+            return;
+        };
+        queue.wake_all();
+    }
+}
@@ -0,0 +1,37 @@
+#![no_std]
+#![no_main]
+
+use std::fs::File;
+use std::sync::{Condvar, Mutex};
+
+const NUM_THREADS: u64 = 4;
+const NUM_INCREMENTS: u64 = 10000;
+
+// (counter, finished threads)
+static STATE: Mutex<(u64, u64)> = Mutex::new((0, 0));
+static FINISHED: Condvar = Condvar::new();
+
+fn increment(_: u64) {
+    for _ in 0..NUM_INCREMENTS {
+        STATE.lock().0 += 1;
+    }
+
+    STATE.lock().1 += 1;
+    FINISHED.notify_all();
+}
+
+#[std::std_main]
+fn main() {
+    for _ in 0..NUM_THREADS {
+        thread::spawn(increment, 0).unwrap();
+    }
+
+    let mut state = STATE.lock();
+    while state.1 < NUM_THREADS {
+        state = FINISHED.wait(state);
+    }
+    assert_eq!(state.0, NUM_THREADS * NUM_INCREMENTS);
+
+    let mut file = File::create("futex_test/result").unwrap();
+    file.write(&state.0.to_le_bytes()).unwrap();
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+use std::fs::File;
+
+#[std::std_main]
+fn main() {
+    let args = args();
+    if args.size() == 1 && args[0] == String::from("spin") {
+        loop {}
+    } else if args.size() == 1 && args[0] == String::from("sibling") {
+        // the spinner is a child of the same parent, not of this process
+        let mut buf = [0u8; 8];
+        assert_eq!(File::open("nice_test/sibling").unwrap().read(&mut buf), Some(8));
+        assert!(!set_nice(u64::from_le_bytes(buf), -20));
+        assert!(!set_affinity(u64::from_le_bytes(buf), 1));
+        return;
+    }
+
+    let pid = get_pid();
+    assert!(set_nice(pid, 19));
+    assert!(set_nice(pid, -20));
+    assert!(!set_nice(pid, 20));
+    assert!(!set_nice(pid, -21));
+    assert!(!set_nice(100000, 0));
+
+    // a busy low priority thread must not stop this one from finishing
+    assert!(set_nice(pid, 0));
+    let spinner = thread::spawn(|_| loop {}, 0).unwrap();
+    assert!(set_nice(spinner.tid(), 19));
+    for _ in 0..10 {
+        sleep(1);
+    }
+
+    // only its own threads and children can be changed or pinned
+    let child = spawn_with_args("test_program11", &["spin"], &[]).unwrap();
+    assert!(set_nice(child, 19));
+    assert!(set_affinity(child, 1));
+    File::create("nice_test/sibling").unwrap().write(&child.to_le_bytes()).unwrap();
+    let sibling = spawn_with_args("test_program11", &["sibling"], &[]).unwrap();
+    assert_eq!(wait(sibling), Some(0));
+    assert!(kill(child));
+    assert_eq!(wait(child), Some(-1));
+
+    File::create("nice_test/result").unwrap();
+}
@@ -0,0 +1,19 @@
+#![no_std]
+#![no_main]
+
+use std::fs::File;
+
+#[std::std_main]
+fn main() {
+    // the kernel types "hi", a backspace and "ey" followed by enter
+    let line = io::stdin().read_line().unwrap();
+    assert_eq!(line, String::from("hey"));
+
+    // only the release of enter is left
+    let event = io::try_read_key().unwrap();
+    assert_eq!(event.value, io::KEY_RELEASED);
+    assert_eq!(event.char, Some('\n'));
+    assert!(io::try_read_key().is_none());
+
+    File::create("key_test/result").unwrap();
+}
@@ -0,0 +1,68 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use std::fs::File;
+use std::sync::Mutex;
+
+const NUM_THREADS: u64 = 4;
+const NUM_ADDS: u64 = 1000;
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+// the threads of the child wait for it on a page that was shared copy-on-write with the parent
+static SUM: Mutex<u64> = Mutex::new(0);
+
+fn count(_: u64) {
+    loop {
+        COUNTER.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// only the main thread can fork
+fn fork_from_thread(_: u64) {
+    assert!(fork().is_none());
+}
+
+fn add(value: u64) {
+    for _ in 0..NUM_ADDS {
+        *SUM.lock() += value;
+    }
+}
+
+#[std::std_main]
+fn main() {
+    assert_eq!(thread::spawn(fork_from_thread, 0).unwrap().join(), Some(0));
+
+    *SUM.lock() = 0;
+
+    // the thread keeps writing on another core while the process forks
+    thread::spawn(count, 0).unwrap();
+    while COUNTER.load(Ordering::Relaxed) == 0 {}
+
+    match fork().unwrap() {
+        ForkResult::Child => {
+            // the counter of the parent is not shared anymore
+            let counter = COUNTER.load(Ordering::Relaxed);
+            sleep(10);
+            assert_eq!(COUNTER.load(Ordering::Relaxed), counter);
+
+            // the child has none of the threads of the parent, so it can create its own
+            let mut handles = Vec::new();
+            for i in 1..=NUM_THREADS {
+                handles.push(thread::spawn(add, i).unwrap());
+            }
+            while let Some(handle) = handles.pop() {
+                assert_eq!(handle.join(), Some(0));
+            }
+            assert_eq!(*SUM.lock(), NUM_ADDS * NUM_THREADS * (NUM_THREADS + 1) / 2);
+        }
+        ForkResult::Parent(pid) => {
+            assert_eq!(wait(pid), Some(0));
+            let counter = COUNTER.load(Ordering::Relaxed);
+            sleep(10);
+            assert!(COUNTER.load(Ordering::Relaxed) > counter);
+
+            File::create("fork_test/result").unwrap();
+        }
+    }
+}
@@ -0,0 +1,29 @@
+#![no_std]
+#![no_main]
+
+use std::fs::File;
+
+fn finish(_: u64) {}
+
+// the thread it starts is not joined either, its parent is not the main thread
+fn start_and_finish(_: u64) {
+    thread::spawn(finish, 0).unwrap();
+}
+
+#[std::std_main]
+fn main() {
+    let args = args();
+
+    if args.size() == 0 {
+        // started by the kernel, so start itself as a child and wait for it
+        let pid = spawn_with_args("test_program14", &["child"], &[]).unwrap();
+        assert_eq!(wait(pid), Some(0));
+
+        File::create("exit_test/result").unwrap();
+    } else {
+        // the threads have exited by the time the main thread does, but nobody joined them
+        thread::spawn(finish, 0).unwrap();
+        thread::spawn(start_and_finish, 0).unwrap();
+        sleep(10);
+    }
+}
@@ -0,0 +1,10 @@
+#![no_std]
+#![no_main]
+
+#[std::std_main]
+fn main() {
+    for _ in 0..10 {
+        let pid = spawn("test_program2").unwrap();
+        assert_eq!(wait(pid), Some(0));
+    }
+}
@@ -0,0 +1,20 @@
+#![no_std]
+#![no_main]
+
+use std::fs::File;
+
+#[std::std_main]
+fn main() {
+    let mut file = File::create("fs_test/file").unwrap();
+    assert_eq!(file.write(b"hello world"), Some(11));
+    assert_eq!(file.seek(6), Some(6));
+    assert_eq!(file.write(b"there"), Some(5));
+
+    let mut buf = [0u8; 32];
+    assert_eq!(file.seek(0), Some(0));
+    assert_eq!(file.read(&mut buf), Some(11));
+    assert_eq!(&buf[..11], b"hello there");
+    assert_eq!(file.read(&mut buf), Some(0));
+
+    assert!(File::open("fs_test/missing").is_none());
+}
@@ -0,0 +1,29 @@
+#![no_std]
+#![no_main]
+
+use std::fs::{pipe, File};
+
+#[std::std_main]
+fn main() {
+    let (mut read_end, write_end) = pipe().unwrap();
+    let pid = spawn_with_files("test_program6", &[&write_end]).unwrap();
+    drop(write_end);
+
+    let mut total = 0;
+    let mut buf = [0u8; 100];
+    loop {
+        let size = read_end.read(&mut buf).unwrap();
+        if size == 0 {
+            break;
+        }
+        for byte in &buf[..size] {
+            assert_eq!(*byte, (total % 256) as u8);
+            total += 1;
+        }
+    }
+
+    assert_eq!(wait(pid), Some(0));
+
+    let mut result = File::create("pipe_test/result").unwrap();
+    result.write(&(total as u64).to_le_bytes()).unwrap();
+}
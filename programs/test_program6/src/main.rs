@@ -0,0 +1,20 @@
+#![no_std]
+#![no_main]
+
+use std::fs::File;
+
+#[std::std_main]
+fn main() {
+    // the write end of the pipe is handed over as the first file descriptor
+    let mut pipe = File::from_fd(0);
+
+    let mut data = [0u8; 10000];
+    for i in 0..data.len() {
+        data[i] = (i % 256) as u8;
+    }
+
+    let mut written = 0;
+    while written < data.len() {
+        written += pipe.write(&data[written..]).unwrap();
+    }
+}
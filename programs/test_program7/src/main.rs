@@ -0,0 +1,25 @@
+#![no_std]
+#![no_main]
+
+use std::env::{set_var, var};
+use std::fs::File;
+
+#[std::std_main]
+fn main() {
+    let args = args();
+
+    if args.size() == 0 {
+        // started by the kernel, so start itself as the child
+        set_var("GREETING", "hello");
+        let pid = spawn_with_args("test_program7", &["child", "two words"], &[]).unwrap();
+        assert_eq!(wait(pid), Some(0));
+
+        File::create("args_test/result").unwrap();
+    } else {
+        assert_eq!(args.size(), 2);
+        assert!(args[0] == String::from("child"));
+        assert!(args[1] == String::from("two words"));
+        assert!(var("GREETING") == Some(String::from("hello")));
+        assert!(var("MISSING").is_none());
+    }
+}
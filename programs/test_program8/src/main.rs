@@ -0,0 +1,22 @@
+#![no_std]
+#![no_main]
+
+use std::fs::File;
+
+#[std::std_main]
+fn main() {
+    let args = args();
+
+    if args.size() == 0 {
+        // started by the kernel, so start itself as a child that never exits
+        let pid = spawn_with_args("test_program8", &["spin"], &[]).unwrap();
+        sleep(10);
+        assert!(kill(pid));
+        assert_eq!(wait(pid), Some(-1));
+        assert!(!kill(pid));
+
+        File::create("kill_test/result").unwrap();
+    } else {
+        loop {}
+    }
+}
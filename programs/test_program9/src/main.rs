@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use std::fs::File;
+
+const NUM_THREADS: u64 = 4;
+const PART_SIZE: u64 = 100000;
+
+static SUM: AtomicU64 = AtomicU64::new(0);
+
+fn add_part(part: u64) {
+    let mut sum = 0;
+    for i in part * PART_SIZE..(part + 1) * PART_SIZE {
+        sum += i;
+    }
+    SUM.fetch_add(sum, Ordering::Relaxed);
+}
+
+fn spin(_: u64) {
+    loop {}
+}
+
+#[std::std_main]
+fn main() {
+    let mut handles = Vec::new();
+    for part in 0..NUM_THREADS {
+        handles.push(thread::spawn(add_part, part).unwrap());
+    }
+    while let Some(handle) = handles.pop() {
+        assert_eq!(handle.join(), Some(0));
+    }
+
+    let n = NUM_THREADS * PART_SIZE;
+    assert_eq!(SUM.load(Ordering::Relaxed), n * (n - 1) / 2);
+
+    // the process ends with the main thread even though this one never exits
+    thread::spawn(spin, 0).unwrap();
+
+    let mut file = File::create("thread_test/result").unwrap();
+    file.write(&SUM.load(Ordering::Relaxed).to_le_bytes()).unwrap();
+}
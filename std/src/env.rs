@@ -0,0 +1,52 @@
+use kernel_std::{deserialize, Mutable, String, Vec};
+
+static ARGS: Mutable<Option<Vec<String>>> = Mutable::new(None);
+static VARS: Mutable<Option<Vec<(String, String)>>> = Mutable::new(None);
+
+// the kernel stores the size of the data at addr, followed by the serialized arguments and environment
+pub(crate) fn init_env(addr: u64) {
+    let size = unsafe { *(addr as *const u64) } as usize;
+    let data = Vec::new_from_slice(unsafe { core::slice::from_raw_parts((addr + 8) as *const u8, size) });
+    let (args, vars) = deserialize::<(Vec<String>, Vec<(String, String)>)>(&data);
+
+    let t = ARGS.borrow();
+    *ARGS.get_mut(&t) = Some(args);
+    ARGS.release(t);
+
+    let t = VARS.borrow();
+    *VARS.get_mut(&t) = Some(vars);
+    VARS.release(t);
+}
+
+// the arguments the program was started with (without the program path)
+pub fn args() -> Vec<String> {
+    let t = ARGS.borrow();
+    let res = ARGS.get(&t).clone().unwrap_or_default();
+    ARGS.release(t);
+    res
+}
+
+pub fn vars() -> Vec<(String, String)> {
+    let t = VARS.borrow();
+    let res = VARS.get(&t).clone().unwrap_or_default();
+    VARS.release(t);
+    res
+}
+
+pub fn var(key: &str) -> Option<String> {
+    let key = String::from(key);
+    let t = VARS.borrow();
+    let res = VARS.get(&t).as_ref().and_then(|vars| vars.into_iter().find(|(k, _)| *k == key).map(|(_, v)| v.clone()));
+    VARS.release(t);
+    res
+}
+
+// also changes the environment that spawned children get
+pub fn set_var(key: &str, value: &str) {
+    let key = String::from(key);
+    let t = VARS.borrow();
+    let vars = VARS.get_mut(&t).get_or_insert_with(Vec::new);
+    vars.retain(&|(k, _)| *k != key);
+    vars.push((key, String::from(value)));
+    VARS.release(t);
+}
@@ -0,0 +1,66 @@
+use crate::syscall::{syscall1r, syscall2r, syscall3r, SyscallCode, SYSCALL_ERROR};
+
+pub struct File {
+    fd: u64,
+}
+
+const fn check_result(res: u64) -> Option<u64> {
+    if res == SYSCALL_ERROR {
+        None
+    } else {
+        Some(res)
+    }
+}
+
+impl File {
+    // takes ownership of a file descriptor, for example one that was handed over by the parent
+    pub const fn from_fd(fd: u64) -> Self {
+        Self { fd }
+    }
+
+    pub const fn fd(&self) -> u64 {
+        self.fd
+    }
+
+    fn open_with(path: &str, create: bool) -> Option<Self> {
+        let fd = check_result(syscall3r(SyscallCode::Open, path.as_ptr() as u64, path.len() as u64, create as u64))?;
+        Some(Self { fd })
+    }
+
+    // opens an existing file
+    pub fn open(path: &str) -> Option<Self> {
+        Self::open_with(path, false)
+    }
+
+    // opens a file and creates it if it does not exist
+    pub fn create(path: &str) -> Option<Self> {
+        Self::open_with(path, true)
+    }
+
+    // returns the number of bytes read, 0 means the end of file
+    pub fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        check_result(syscall3r(SyscallCode::Read, self.fd, buf.as_mut_ptr() as u64, buf.len() as u64)).map(|size| size as usize)
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> Option<usize> {
+        check_result(syscall3r(SyscallCode::Write, self.fd, buf.as_ptr() as u64, buf.len() as u64)).map(|size| size as usize)
+    }
+
+    // moves to the offset from the start of the file
+    pub fn seek(&mut self, offset: u64) -> Option<u64> {
+        check_result(syscall2r(SyscallCode::Seek, self.fd, offset))
+    }
+}
+
+// returns (read end, write end), reading blocks until there is data and writing blocks while the pipe is full
+pub fn pipe() -> Option<(File, File)> {
+    let mut fds = [0u64; 2];
+    check_result(syscall1r(SyscallCode::Pipe, fds.as_mut_ptr() as u64))?;
+    Some((File::from_fd(fds[0]), File::from_fd(fds[1])))
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        syscall1r(SyscallCode::Close, self.fd);
+    }
+}
@@ -0,0 +1,81 @@
+use kernel_std::String;
+use crate::syscall::{syscall1r, SyscallCode, SYSCALL_ERROR};
+
+pub const KEY_RELEASED: i32 = 0;
+pub const KEY_PRESSED: i32 = 1;
+pub const KEY_REPEATED: i32 = 2;
+
+#[derive(Clone, Copy, Debug)]
+pub struct KeyEvent {
+    pub code: u16,
+    pub value: i32, // KEY_RELEASED, KEY_PRESSED or KEY_REPEATED
+    pub char: Option<char>, // what the key types, '\n' for enter and '\x08' for backspace
+}
+
+// the kernel puts the value in the low 32 bits, then the key code and the character
+fn read_key_with(blocking: bool) -> Option<KeyEvent> {
+    let res = syscall1r(SyscallCode::ReadKey, blocking as u64);
+    if res == SYSCALL_ERROR || res == 0 {
+        return None;
+    }
+    let c = (res >> 48) as u8;
+    Some(KeyEvent {
+        code: (res >> 32) as u16,
+        value: res as u32 as i32,
+        char: if c == 0 { None } else { Some(c as char) },
+    })
+}
+
+// waits for the next key event, a process in the background waits until it is in the foreground
+pub fn read_key() -> Option<KeyEvent> {
+    read_key_with(true)
+}
+
+// None if there is no key event right now or the process is not in the foreground
+pub fn try_read_key() -> Option<KeyEvent> {
+    read_key_with(false)
+}
+
+// the characters typed on the keyboard while the process is in the foreground
+pub struct Stdin;
+
+pub fn stdin() -> Stdin {
+    Stdin
+}
+
+impl Stdin {
+    // waits for the first character and returns how many were read
+    pub fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        let mut size = 0;
+        while size < buf.len() {
+            let event = if size == 0 {
+                read_key()?
+            } else if let Some(event) = try_read_key() {
+                event
+            } else {
+                break;
+            };
+            if let (Some(c), KEY_PRESSED | KEY_REPEATED) = (event.char, event.value) {
+                buf[size] = c as u8;
+                size += 1;
+            }
+        }
+        Some(size)
+    }
+
+    // reads until enter is pressed, backspace removes the last character
+    pub fn read_line(&mut self) -> Option<String> {
+        let mut line = String::new();
+        loop {
+            let mut c = [0u8];
+            self.read(&mut c)?;
+            match c[0] {
+                b'\n' => return Some(line),
+                b'\x08' => {
+                    line.pop();
+                }
+                c => line.push(c as char),
+            }
+        }
+    }
+}
@@ -1,6 +1,12 @@
 #![no_std]
 
 mod syscall;
+pub mod env;
+pub mod fs;
+pub mod io;
+pub mod sync;
+pub mod thread;
+pub mod time;
 
 use core::fmt;
 use core::fmt::Write;
@@ -9,7 +15,10 @@ use kernel_std::{init_print, init_std_memory};
 
 pub use kernel_std::{print, println, Vec, String, Box, Mutable};
 pub use std_derive::main as std_main;
-use crate::syscall::{syscall0, syscall0r, syscall1, syscall2, SyscallCode};
+pub use env::args;
+use crate::env::{init_env, vars};
+use crate::fs::File;
+use crate::syscall::{syscall0r, syscall1, syscall1r, syscall2, syscall2r, syscall4r, SyscallCode, FORK_CHILD, SYSCALL_ERROR};
 
 extern "C" {
     fn main();
@@ -18,7 +27,7 @@ extern "C" {
 #[doc(hidden)]
 pub fn _on_panic(info: &PanicInfo) -> ! {
     println!("{}", info);
-    exit();
+    exit(1);
 }
 
 fn alloc_page(addr: *mut u8, ignore_if_exists: bool) {
@@ -30,24 +39,26 @@ fn dealloc_page(addr: *mut u8) {
 }
 
 #[doc(hidden)]
-pub fn _init() -> ! {
+pub fn _init(args_addr: u64) -> ! {
     let ram_start = 1u64 << 34;
     let frame_size = 1u64 << 30;
     init_std_memory(&alloc_page, &dealloc_page, ram_start + frame_size);
 
     init_print(&_print);
+    init_env(args_addr);
 
     unsafe {
         main();
     }
 
-    exit();
+    exit(0);
 }
 
 fn print_str(s: &str) {
     syscall2(SyscallCode::PrintStr, s.as_ptr() as u64, s.len() as u64);
 }
 
+// milliseconds since the machine started
 pub fn get_ticks() -> u64 {
     syscall0r(SyscallCode::GetTicks)
 }
@@ -82,13 +93,124 @@ fn _print(args: fmt::Arguments) {
     writer.flush();
 }
 
-pub fn exit() -> ! {
-    syscall0(SyscallCode::Exit);
+pub fn exit(code: i32) -> ! {
+    syscall1(SyscallCode::Exit, code as u64);
     loop {}
 }
 
+// writes the disk back and turns off the machine, qemu exits with the status.
+// only returns, with false, if the process was spawned by another process instead of the kernel
+pub fn shutdown(status: i32) -> bool {
+    syscall1r(SyscallCode::Shutdown, status as u64) != SYSCALL_ERROR
+}
+
+// writes the disk back and restarts the machine, returns false like shutdown
+pub fn reboot() -> bool {
+    syscall0r(SyscallCode::Reboot) != SYSCALL_ERROR
+}
+
+// see time::sleep for other units
 pub fn sleep(ms: u64) {
     syscall1(SyscallCode::Sleep, ms);
 }
 
+// starts the program at path and returns its pid
+pub fn spawn(path: &str) -> Option<u64> {
+    spawn_with_args(path, &[], &[])
+}
+
+pub fn spawn_with_files(path: &str, files: &[&File]) -> Option<u64> {
+    spawn_with_args(path, &[], files)
+}
+
+fn push_block_entry(block: &mut Vec<u8>, prefix: &str, entry: &String) {
+    for b in prefix.bytes() {
+        block.push(b);
+    }
+    for c in entry {
+        for b in c.encode_utf8(&mut [0; 4]).bytes() {
+            block.push(b);
+        }
+    }
+    block.push(0);
+}
+
+// the child gets the arguments, this process's environment and its own copies of the files as file descriptors 0, 1, 2...
+pub fn spawn_with_args(path: &str, args: &[&str], files: &[&File]) -> Option<u64> {
+    // the path, arguments and environment are passed to the kernel in one block, each entry terminated by 0
+    let mut block = Vec::new();
+    push_block_entry(&mut block, "", &String::from(path));
+    for arg in args {
+        push_block_entry(&mut block, "A", &String::from(arg));
+    }
+    for (key, value) in vars() {
+        let mut entry = key;
+        entry.push('=');
+        for c in &value {
+            entry.push(*c);
+        }
+        push_block_entry(&mut block, "E", &entry);
+    }
+
+    let mut fds = Vec::new();
+    for file in files {
+        fds.push(file.fd());
+    }
+
+    let pid = syscall4r(SyscallCode::Spawn, block.as_ptr() as u64, block.size() as u64, fds.as_ptr() as u64, fds.size() as u64);
+    if pid == SYSCALL_ERROR {
+        None
+    } else {
+        Some(pid)
+    }
+}
+
+pub enum ForkResult {
+    Parent(u64), // the pid of the child
+    Child,
+}
+
+// the child gets a copy of the memory and files of this process and continues from here without the other threads,
+// memory is only copied when one of them writes to it. only the main thread can fork, None in other threads
+pub fn fork() -> Option<ForkResult> {
+    match syscall0r(SyscallCode::Fork) {
+        SYSCALL_ERROR => None,
+        FORK_CHILD => Some(ForkResult::Child),
+        pid => Some(ForkResult::Parent(pid)),
+    }
+}
+
+// blocks until the child with this pid exits and returns its exit code
+pub fn wait(pid: u64) -> Option<i32> {
+    let res = syscall1r(SyscallCode::Wait, pid);
+    if res == SYSCALL_ERROR {
+        None
+    } else {
+        Some(res as u32 as i32)
+    }
+}
+
+// ends a child or a thread of this process, its parent sees -1 as the exit code
+pub fn kill(pid: u64) -> bool {
+    syscall1r(SyscallCode::Kill, pid) != SYSCALL_ERROR
+}
+
+// from -20 (most cpu time) to 19 (least cpu time), children start with the value of their parent.
+// like kill, only for the threads of this process and its children
+pub fn set_nice(pid: u64, nice: i32) -> bool {
+    syscall2r(SyscallCode::SetNice, pid, nice as i64 as u64) != SYSCALL_ERROR
+}
+
+// how far in bytes the stack of the main thread may grow, from 128 kB up to 8 MB, 1 MB by default.
+// a forked child keeps the limit, a spawned one gets the default
+pub fn set_stack_limit(limit: u64) -> bool {
+    syscall1r(SyscallCode::SetStackLimit, limit) != SYSCALL_ERROR
+}
+
+// bit i of the mask allows the process to run on core i, children start with the mask of their parent.
+// like kill, only for the threads of this process and its children
+pub fn set_affinity(pid: u64, mask: u64) -> bool {
+    syscall2r(SyscallCode::SetAffinity, pid, mask) != SYSCALL_ERROR
+}
+
 
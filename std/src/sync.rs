@@ -0,0 +1,115 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::sleep;
+use crate::syscall::{syscall2, syscall2r, SyscallCode, FUTEX_TABLE_FULL};
+
+// sleeps while the value at addr is expected, may return early
+fn futex_wait(addr: &AtomicU32, expected: u32) {
+    if syscall2r(SyscallCode::FutexWait, addr.as_ptr() as u64, expected as u64) == FUTEX_TABLE_FULL {
+        // the kernel could not queue us, so back off instead of spinning and let the caller check again
+        sleep(1);
+    }
+}
+
+// wakes up to count threads sleeping on addr
+fn futex_wake(addr: &AtomicU32, count: u32) {
+    syscall2(SyscallCode::FutexWake, addr.as_ptr() as u64, count as u64);
+}
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+const CONTENDED: u32 = 2; // locked and somebody may be sleeping on it
+
+// unlike Mutable, waiting threads sleep in the kernel instead of spinning
+pub struct Mutex<T> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+unsafe impl<T: Send> Send for Mutex<T> {}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self { state: AtomicU32::new(UNLOCKED), data: UnsafeCell::new(data) }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if self.state.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            // whoever unlocks has to wake us up, so the state stays contended while anybody sleeps
+            while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+                futex_wait(&self.state, CONTENDED);
+            }
+        }
+        MutexGuard { mutex: self }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self.state.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+            Some(MutexGuard { mutex: self })
+        } else {
+            None
+        }
+    }
+
+    fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex_wake(&self.state, 1);
+        }
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+pub struct Condvar {
+    // changes with every notify, so a notify between unlocking and sleeping is not missed
+    sequence: AtomicU32,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self { sequence: AtomicU32::new(0) }
+    }
+
+    // unlocks the mutex while sleeping, wakeups can be spurious so the condition has to be checked in a loop
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        let sequence = self.sequence.load(Ordering::Relaxed);
+        drop(guard);
+        futex_wait(&self.sequence, sequence);
+        mutex.lock()
+    }
+
+    pub fn notify_one(&self) {
+        self.sequence.fetch_add(1, Ordering::Relaxed);
+        futex_wake(&self.sequence, 1);
+    }
+
+    pub fn notify_all(&self) {
+        self.sequence.fetch_add(1, Ordering::Relaxed);
+        futex_wake(&self.sequence, u32::MAX);
+    }
+}
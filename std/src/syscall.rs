@@ -1,5 +1,12 @@
 use core::arch::asm;
 
+// returned by the kernel when a syscall fails
+pub const SYSCALL_ERROR: u64 = u64::MAX;
+// returned to the child of a fork instead of a pid
+pub const FORK_CHILD: u64 = u64::MAX - 1;
+// returned by futex wait when the kernel has no room to put another thread to sleep
+pub const FUTEX_TABLE_FULL: u64 = u64::MAX - 1;
+
 #[repr(u64)]
 pub enum SyscallCode {
     PrintStr = 1,
@@ -9,12 +16,27 @@ pub enum SyscallCode {
     AllocPage = 5,
     DeallocPage = 6,
     Sleep = 7,
-}
-
-pub fn syscall0(code: SyscallCode) {
-    unsafe {
-        asm!("ecall", in("a7") code as u64);
-    }
+    Spawn = 8,
+    Wait = 9,
+    Open = 10,
+    Read = 11,
+    Write = 12,
+    Seek = 13,
+    Close = 14,
+    Pipe = 15,
+    Kill = 16,
+    CreateThread = 17,
+    FutexWait = 18,
+    FutexWake = 19,
+    SetNice = 20,
+    SetAffinity = 21,
+    ReadKey = 22,
+    GetTime = 23,
+    GetUnixTime = 24,
+    Shutdown = 25,
+    Reboot = 26,
+    Fork = 27,
+    SetStackLimit = 28,
 }
 
 pub fn syscall1(code: SyscallCode, arg1: u64) {
@@ -35,4 +57,36 @@ pub fn syscall0r(code: SyscallCode) -> u64 {
         asm!("ecall", in("a7") code as u64, out("a2") ret);
     }
     ret
+}
+
+pub fn syscall1r(code: SyscallCode, arg1: u64) -> u64 {
+    let ret: u64;
+    unsafe {
+        asm!("ecall", in("a7") code as u64, in("a3") arg1, out("a2") ret);
+    }
+    ret
+}
+
+pub fn syscall2r(code: SyscallCode, arg1: u64, arg2: u64) -> u64 {
+    let ret: u64;
+    unsafe {
+        asm!("ecall", in("a7") code as u64, in("a3") arg1, in("a4") arg2, out("a2") ret);
+    }
+    ret
+}
+
+pub fn syscall3r(code: SyscallCode, arg1: u64, arg2: u64, arg3: u64) -> u64 {
+    let ret: u64;
+    unsafe {
+        asm!("ecall", in("a7") code as u64, in("a3") arg1, in("a4") arg2, in("a5") arg3, out("a2") ret);
+    }
+    ret
+}
+
+pub fn syscall4r(code: SyscallCode, arg1: u64, arg2: u64, arg3: u64, arg4: u64) -> u64 {
+    let ret: u64;
+    unsafe {
+        asm!("ecall", in("a7") code as u64, in("a3") arg1, in("a4") arg2, in("a5") arg3, in("a6") arg4, out("a2") ret);
+    }
+    ret
 }
\ No newline at end of file
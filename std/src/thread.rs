@@ -0,0 +1,37 @@
+use core::mem::transmute;
+use crate::syscall::{syscall3r, SyscallCode, SYSCALL_ERROR};
+use crate::{exit, wait};
+
+// threads share the memory and files of the process, exit only ends the calling thread
+// unless it is the main one, then all threads are ended
+pub struct JoinHandle {
+    tid: u64,
+}
+
+impl JoinHandle {
+    pub fn tid(&self) -> u64 {
+        self.tid
+    }
+
+    // blocks until the thread exits and returns its exit code, only the thread that spawned it can join it
+    pub fn join(self) -> Option<i32> {
+        wait(self.tid)
+    }
+}
+
+// the kernel starts the thread here with the function and its argument in a0 and a1
+extern "C" fn thread_entry(f: usize, arg: u64) -> ! {
+    let f = unsafe { transmute::<usize, fn(u64)>(f) };
+    f(arg);
+    exit(0);
+}
+
+// runs f(arg) in a new thread that can be scheduled on any core
+pub fn spawn(f: fn(u64), arg: u64) -> Option<JoinHandle> {
+    let tid = syscall3r(SyscallCode::CreateThread, thread_entry as extern "C" fn(usize, u64) -> ! as usize as u64, f as usize as u64, arg);
+    if tid == SYSCALL_ERROR {
+        None
+    } else {
+        Some(JoinHandle { tid })
+    }
+}
@@ -0,0 +1,64 @@
+use crate::syscall::{syscall0r, syscall1, SyscallCode};
+
+pub use core::time::Duration;
+pub use kernel_std::DateTime;
+
+// nanoseconds since the machine started
+pub fn now_ns() -> u64 {
+    syscall0r(SyscallCode::GetTime)
+}
+
+// a point in monotonic time, the same clock on every core
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Instant {
+    ns: u64,
+}
+
+impl Instant {
+    pub fn now() -> Self {
+        Self { ns: now_ns() }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Self::now().duration_since(*self)
+    }
+
+    // zero if earlier is not before self
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.ns.saturating_sub(earlier.ns))
+    }
+}
+
+// the kernel sleeps in whole milliseconds, so the duration is rounded up
+pub fn sleep(duration: Duration) {
+    let ms = duration.as_nanos().div_ceil(1_000_000);
+    syscall1(SyscallCode::Sleep, ms as u64);
+}
+
+// a point in wall-clock time from the real time clock, it is not monotonic
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct SystemTime {
+    ns: u64, // since 1970-01-01 00:00:00 UTC
+}
+
+impl SystemTime {
+    pub const UNIX_EPOCH: SystemTime = SystemTime { ns: 0 };
+
+    pub fn now() -> Self {
+        Self { ns: syscall0r(SyscallCode::GetUnixTime) }
+    }
+
+    // None if earlier is after self
+    pub fn duration_since(&self, earlier: SystemTime) -> Option<Duration> {
+        self.ns.checked_sub(earlier.ns).map(Duration::from_nanos)
+    }
+
+    // whole seconds since 1970-01-01 00:00:00 UTC
+    pub fn unix_secs(&self) -> u64 {
+        self.ns / 1_000_000_000
+    }
+
+    pub fn date_time(&self) -> DateTime {
+        DateTime::from_unix_secs(self.unix_secs())
+    }
+}
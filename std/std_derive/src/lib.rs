@@ -10,8 +10,8 @@ pub fn main(_attr: TokenStream, item: TokenStream) -> TokenStream {
         use std::*;
         core::arch::global_asm!(".section .init\n _start: j rust_entry");
         #[no_mangle]
-        extern "C" fn rust_entry() -> ! {
-            std::_init();
+        extern "C" fn rust_entry(args_addr: u64) -> ! {
+            std::_init(args_addr);
         }
         #[panic_handler]
         fn panic(info: &core::panic::PanicInfo) -> ! {